@@ -3,6 +3,15 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
+use rand::Rng;
+
+/// Solid angle density of picking the point of `rec` uniformly on a surface
+/// of `area`, seen along `v`.
+pub fn area_pdf(rec: &HitRecord, v: Vec3, area: f64) -> f64 {
+    let distance_squared = rec.t * rec.t * v.squared_length();
+    let cosine = (Vec3::dot(v, rec.normal) / v.length()).abs();
+    distance_squared / (cosine * area)
+}
 
 pub struct XyRect<Material> {
     pub x0: f64,
@@ -27,7 +36,7 @@ impl<M: Material> XyRect<M> {
 }
 
 impl<M: Material> Hittable for XyRect<M> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.origin.z()) / r.direction.z();
         if t < t_min || t > t_max {
             return None;
@@ -52,6 +61,41 @@ impl<M: Material> Hittable for XyRect<M> {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        match self.hit(Ray::new(origin, v, time), 0.001, f64::INFINITY) {
+            Some(rec) => area_pdf(&rec, v, (self.x1 - self.x0) * (self.y1 - self.y0)),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, _time: f64) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let point = Vec3::new(
+            self.x0 + (self.x1 - self.x0) * rng.gen::<f64>(),
+            self.y0 + (self.y1 - self.y0) * rng.gen::<f64>(),
+            self.k,
+        );
+        point - origin
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mp.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let mut rng = rand::thread_rng();
+        let (u, v) = (rng.gen::<f64>(), rng.gen::<f64>());
+        let a = self.x0 + (self.x1 - self.x0) * u;
+        let b = self.y0 + (self.y1 - self.y0) * v;
+        let p = Vec3::new(a, b, self.k);
+        let rec = HitRecord::on_surface(p, u, v, Vec3::new(0.0, 0.0, 1.0), time, &self.mp);
+        Some((rec, 1.0 / ((self.x1 - self.x0) * (self.y1 - self.y0))))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some((self.x1 - self.x0) * (self.y1 - self.y0))
+    }
 }
 
 pub struct XzRect<Material> {
@@ -77,7 +121,7 @@ impl<M: Material> XzRect<M> {
 }
 
 impl<M: Material> Hittable for XzRect<M> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.origin.y()) / r.direction.y();
         if t < t_min || t > t_max {
             return None;
@@ -102,6 +146,41 @@ impl<M: Material> Hittable for XzRect<M> {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        match self.hit(Ray::new(origin, v, time), 0.001, f64::INFINITY) {
+            Some(rec) => area_pdf(&rec, v, (self.x1 - self.x0) * (self.z1 - self.z0)),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, _time: f64) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let point = Vec3::new(
+            self.x0 + (self.x1 - self.x0) * rng.gen::<f64>(),
+            self.k,
+            self.z0 + (self.z1 - self.z0) * rng.gen::<f64>(),
+        );
+        point - origin
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mp.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let mut rng = rand::thread_rng();
+        let (u, v) = (rng.gen::<f64>(), rng.gen::<f64>());
+        let a = self.x0 + (self.x1 - self.x0) * u;
+        let b = self.z0 + (self.z1 - self.z0) * v;
+        let p = Vec3::new(a, self.k, b);
+        let rec = HitRecord::on_surface(p, u, v, Vec3::new(0.0, 1.0, 0.0), time, &self.mp);
+        Some((rec, 1.0 / ((self.x1 - self.x0) * (self.z1 - self.z0))))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some((self.x1 - self.x0) * (self.z1 - self.z0))
+    }
 }
 
 pub struct YzRect<Material> {
@@ -127,7 +206,7 @@ impl<M: Material> YzRect<M> {
 }
 
 impl<M: Material> Hittable for YzRect<M> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.origin.x()) / r.direction.x();
         if t < t_min || t > t_max {
             return None;
@@ -152,4 +231,39 @@ impl<M: Material> Hittable for YzRect<M> {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        match self.hit(Ray::new(origin, v, time), 0.001, f64::INFINITY) {
+            Some(rec) => area_pdf(&rec, v, (self.y1 - self.y0) * (self.z1 - self.z0)),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, _time: f64) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let point = Vec3::new(
+            self.k,
+            self.y0 + (self.y1 - self.y0) * rng.gen::<f64>(),
+            self.z0 + (self.z1 - self.z0) * rng.gen::<f64>(),
+        );
+        point - origin
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mp.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let mut rng = rand::thread_rng();
+        let (u, v) = (rng.gen::<f64>(), rng.gen::<f64>());
+        let a = self.y0 + (self.y1 - self.y0) * u;
+        let b = self.z0 + (self.z1 - self.z0) * v;
+        let p = Vec3::new(self.k, a, b);
+        let rec = HitRecord::on_surface(p, u, v, Vec3::new(1.0, 0.0, 0.0), time, &self.mp);
+        Some((rec, 1.0 / ((self.y1 - self.y0) * (self.z1 - self.z0))))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some((self.y1 - self.y0) * (self.z1 - self.z0))
+    }
 }
@@ -0,0 +1,223 @@
+use crate::hittable::HitRecord;
+use crate::material::{Material, Volumetric};
+use crate::phase::PhaseFunction;
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use rand::Rng;
+
+/// Homogeneous fog filling the whole scene outside of any object, for haze and
+/// aerial perspective. The density may fall off exponentially with height
+/// above `base_height`, with `scale_height` the distance over which it drops
+/// by a factor of e; below `base_height` it stays at `density`.
+///
+/// Free flights are sampled by inverting the optical depth analytically, so
+/// rays escaping to the background are handled without a bounding object.
+/// Fog without an extent is infinitely thick along any ray that does not
+/// thin out upwards, so such rays always scatter before reaching the
+/// background; `with_extent` limits it to a ball around the scene.
+pub struct Atmosphere {
+    density: f64,
+    falloff: Option<(f64, f64)>,
+    extent: Option<(Vec3, f64)>,
+    phase_function: Box<dyn Material>,
+}
+
+impl Atmosphere {
+    pub fn new(density: f64, albedo: Box<dyn Texture>, phase: Box<dyn PhaseFunction>) -> Self {
+        Self {
+            density,
+            falloff: None,
+            extent: None,
+            phase_function: Box::new(Volumetric::new(albedo, phase)),
+        }
+    }
+
+    pub fn with_height_falloff(mut self, base_height: f64, scale_height: f64) -> Self {
+        self.falloff = Some((base_height, scale_height));
+        self
+    }
+
+    /// Fills only the ball of `radius` around `center` with fog.
+    pub fn with_extent(mut self, center: Vec3, radius: f64) -> Self {
+        self.extent = Some((center, radius));
+        self
+    }
+
+    pub fn density_at(&self, p: &Vec3) -> f64 {
+        match self.falloff {
+            Some((base, scale)) => self.density * f64::exp(-(p.y() - base).max(0.0) / scale),
+            None => self.density,
+        }
+    }
+
+    /// Splits the part of `[t_min, t_max]` inside the extent where the ray
+    /// crosses `base_height`, so that the density is either constant or
+    /// purely exponential on each piece.
+    fn pieces(&self, r: &Ray, t_min: f64, t_max: f64) -> Vec<(f64, f64)> {
+        let (mut t_min, mut t_max) = (t_min, t_max);
+        if let Some((center, radius)) = self.extent {
+            let oc = r.origin - center;
+            let a = r.direction.squared_length();
+            let half_b = Vec3::dot(oc, r.direction);
+            let c = oc.squared_length() - radius * radius;
+            let discriminant = half_b * half_b - a * c;
+            if discriminant < 0.0 {
+                return Vec::new();
+            }
+            let sqrtd = discriminant.sqrt();
+            t_min = t_min.max((-half_b - sqrtd) / a);
+            t_max = t_max.min((-half_b + sqrtd) / a);
+            if t_min >= t_max {
+                return Vec::new();
+            }
+        }
+        if let Some((base, _)) = self.falloff {
+            let t = (base - r.origin.y()) / r.direction.y();
+            if t > t_min && t < t_max {
+                return vec![(t_min, t), (t, t_max)];
+            }
+        }
+        vec![(t_min, t_max)]
+    }
+
+    /// Whether the piece `[t0, t1]` lies in the exponential part.
+    fn is_thinning(&self, r: &Ray, t0: f64, t1: f64) -> bool {
+        match self.falloff {
+            Some((base, _)) => {
+                let probe = if t1.is_finite() {
+                    0.5 * (t0 + t1)
+                } else {
+                    t0 + 1.0
+                };
+                r.direction.y() != 0.0 && r.at(probe).y() > base
+            }
+            None => false,
+        }
+    }
+
+    /// `exp(-(y - base) / scale)` at parameter `t`, zero at infinity.
+    fn relative_density(&self, r: &Ray, t: f64) -> f64 {
+        let (base, scale) = self.falloff.unwrap();
+        if t.is_finite() {
+            f64::exp(-(r.at(t).y() - base) / scale)
+        } else {
+            0.0
+        }
+    }
+
+    fn piece_optical_depth(&self, r: &Ray, t0: f64, t1: f64) -> f64 {
+        let length = r.direction.length();
+        if !self.is_thinning(r, t0, t1) {
+            return self.density_at(&r.at(t0)) * length * (t1 - t0);
+        }
+        let (_, scale) = self.falloff.unwrap();
+        let e0 = self.relative_density(r, t0);
+        let e1 = self.relative_density(r, t1);
+        self.density * scale * length / r.direction.y() * (e0 - e1)
+    }
+
+    /// Fraction of light surviving along `r` between `t_min` and `t_max`.
+    pub fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.density <= 0.0 {
+            return 1.0;
+        }
+        let depth: f64 = self
+            .pieces(r, t_min, t_max)
+            .into_iter()
+            .map(|(t0, t1)| self.piece_optical_depth(r, t0, t1))
+            .sum();
+        f64::exp(-depth)
+    }
+
+    /// Samples a scattering event in the fog before `t_max`, if any.
+    pub fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if self.density <= 0.0 {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let mut tau = -f64::ln(1.0 - rng.gen::<f64>());
+        for (t0, t1) in self.pieces(&r, t_min, t_max) {
+            let depth = self.piece_optical_depth(&r, t0, t1);
+            if tau > depth {
+                tau -= depth;
+                continue;
+            }
+            let t = if self.is_thinning(&r, t0, t1) {
+                let (base, scale) = self.falloff.unwrap();
+                let dy = r.direction.y();
+                let e = self.relative_density(&r, t0)
+                    - tau * dy / (self.density * scale * r.direction.length());
+                let y = base - scale * e.ln();
+                (y - r.origin.y()) / dy
+            } else {
+                t0 + tau / (self.density_at(&r.at(t0)) * r.direction.length())
+            };
+            let normal = Vec3::new(1.0, 0.0, 0.0);
+            return Some(HitRecord::new(
+                r.at(t),
+                t,
+                0.0,
+                0.0,
+                normal,
+                r,
+                &*self.phase_function,
+            ));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phase::IsotropicPhase;
+    use crate::texture::SolidColor;
+
+    #[test]
+    fn test_free_flights_match_transmittance() {
+        let fog = Atmosphere::new(
+            0.5,
+            Box::new(SolidColor::new(Vec3::one())),
+            Box::new(IsotropicPhase),
+        )
+        .with_height_falloff(1.0, 2.0);
+        // Starts in the constant layer and escapes upwards.
+        let r = Ray::new(Vec3::zero(), Vec3::new(1.0, 1.0, 0.0), 0.0);
+        let expected = f64::exp(-0.5 * 2f64.sqrt() * (1.0 + 2.0));
+        assert!((fog.transmittance(&r, 0.0, f64::INFINITY) - expected).abs() < 1e-12);
+
+        let n = 20000;
+        let escaped = (0..n)
+            .filter(|_| fog.hit(r, 0.0, f64::INFINITY).is_none())
+            .count();
+        assert!((escaped as f64 / n as f64 - expected).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_background_reachable_beyond_extent() {
+        let fog = || {
+            Atmosphere::new(
+                0.1,
+                Box::new(SolidColor::new(Vec3::one())),
+                Box::new(IsotropicPhase),
+            )
+        };
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -2.0), 0.0);
+        assert_eq!(fog().transmittance(&r, 0.0, f64::INFINITY), 0.0);
+
+        let fog = fog().with_extent(Vec3::new(0.0, 0.0, -5.0), 15.0);
+        let expected = f64::exp(-0.1 * 20.0);
+        assert!((fog.transmittance(&r, 0.0, f64::INFINITY) - expected).abs() < 1e-12);
+        let n = 20000;
+        let escaped = (0..n)
+            .filter(|_| fog.hit(r, 0.0, f64::INFINITY).is_none())
+            .count();
+        assert!((escaped as f64 / n as f64 - expected).abs() < 0.02);
+
+        // Rays passing by the fog are not affected at all.
+        let outside = Ray::new(Vec3::new(0.0, 20.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert_eq!(fog.transmittance(&outside, 0.0, f64::INFINITY), 1.0);
+        assert!(fog.hit(outside, 0.0, f64::INFINITY).is_none());
+    }
+}
@@ -0,0 +1,261 @@
+use crate::spectrum::luminance;
+use crate::vec3::Vec3;
+use image::error::{ImageError, ImageResult, ParameterError, ParameterErrorKind};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::path::Path;
+
+/// Radiance of rays escaping the scene, which can also act as a light.
+pub trait Background: Send + Sync {
+    fn value(&self, direction: Vec3) -> Vec3;
+
+    /// Whether `sample` and `pdf` are implemented, in which case light
+    /// sampling accounts for the background at non-specular vertices.
+    fn is_sampleable(&self) -> bool {
+        false
+    }
+
+    /// Random direction, preferring the bright parts of the background.
+    fn sample(&self) -> Vec3 {
+        Vec3::new(0.0, 1.0, 0.0)
+    }
+
+    /// Solid angle density with which `sample` picks `direction`.
+    fn pdf(&self, _direction: Vec3) -> f64 {
+        0.0
+    }
+}
+
+/// The same radiance in every direction.
+pub struct ConstantBackground {
+    pub color: Vec3,
+}
+
+impl ConstantBackground {
+    pub fn new(color: Vec3) -> Self {
+        Self { color }
+    }
+}
+
+impl Background for ConstantBackground {
+    fn value(&self, _direction: Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// Index of the first entry of the cumulative `cdf` above `u * total`.
+fn search_cdf(cdf: &[f64], u: f64) -> usize {
+    let target = u * cdf[cdf.len() - 1];
+    cdf.partition_point(|&c| c <= target).min(cdf.len() - 1)
+}
+
+/// Unrotated direction at map coordinates `(u, v)`.
+fn equirectangular_direction(u: f64, v: f64) -> Vec3 {
+    let phi = 2.0 * PI * u - PI;
+    let theta = PI * v;
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+fn invalid(message: &str) -> ImageError {
+    let kind = ParameterErrorKind::Generic(message.to_string());
+    ImageError::Parameter(ParameterError::from_kind(kind))
+}
+
+/// Equirectangular environment map, e.g. an HDRI of a studio or the sky. Its
+/// top row is straight up (+y) and the centre column looks along +x before
+/// `rotation` about the y axis is applied.
+///
+/// Directions are importance sampled by a piecewise constant distribution
+/// over the pixels, weighted by luminance and by the solid angle of each row.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    rotation: f64,
+    intensity: f64,
+    /// Cumulative pixel weights along each row, one row after the other.
+    conditional_cdf: Vec<f64>,
+    /// Cumulative row weights.
+    marginal_cdf: Vec<f64>,
+}
+
+impl EnvironmentMap {
+    /// Loads a Radiance `.hdr`, OpenEXR or any other format `image` reads.
+    pub fn new(path: &Path) -> ImageResult<Self> {
+        let img = image::open(path)?.into_rgb32f();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let pixels = img
+            .pixels()
+            .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        Self::from_pixels(width, height, pixels)
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Vec3>) -> ImageResult<Self> {
+        if width == 0 || height == 0 {
+            return Err(invalid("empty environment map"));
+        }
+        if width.checked_mul(height) != Some(pixels.len()) {
+            return Err(invalid("pixel count does not match"));
+        }
+        let mut conditional_cdf = Vec::with_capacity(width * height);
+        let mut marginal_cdf = Vec::with_capacity(height);
+        let mut total = 0.0;
+        for j in 0..height {
+            let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+            let mut row = 0.0;
+            for i in 0..width {
+                row += luminance(pixels[j * width + i]).max(0.0) * sin_theta;
+                conditional_cdf.push(row);
+            }
+            total += row;
+            marginal_cdf.push(total);
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+            rotation: 0.0,
+            intensity: 1.0,
+            conditional_cdf,
+            marginal_cdf,
+        })
+    }
+
+    /// Tabulates `radiance` at the centre of each pixel, e.g. to importance
+    /// sample an analytic background.
+    pub fn from_fn(
+        width: usize,
+        height: usize,
+        radiance: impl Fn(Vec3) -> Vec3,
+    ) -> ImageResult<Self> {
+        let mut pixels = Vec::with_capacity(width * height);
+        for j in 0..height {
+            for i in 0..width {
+                let u = (i as f64 + 0.5) / width as f64;
+                let v = (j as f64 + 0.5) / height as f64;
+                pixels.push(radiance(equirectangular_direction(u, v)));
+            }
+        }
+        Self::from_pixels(width, height, pixels)
+    }
+
+    /// Turns the map by `degrees` about the y axis.
+    pub fn with_rotation(mut self, degrees: f64) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    fn rotate(&self, d: Vec3, angle: f64) -> Vec3 {
+        let (sin, cos) = angle.sin_cos();
+        Vec3::new(cos * d.x() + sin * d.z(), d.y(), -sin * d.x() + cos * d.z())
+    }
+
+    /// Map coordinates in `[0, 1]^2` of a world space direction.
+    fn direction_to_uv(&self, direction: Vec3) -> (f64, f64) {
+        let d = Vec3::unit_vector(self.rotate(direction, -self.rotation));
+        let u = (f64::atan2(d.z(), d.x()) + PI) / (2.0 * PI);
+        let v = d.y().clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        self.rotate(equirectangular_direction(u, v), self.rotation)
+    }
+
+    fn pixel_index(&self, u: f64, v: f64) -> (usize, usize) {
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        (i, j)
+    }
+
+    fn total_weight(&self) -> f64 {
+        self.marginal_cdf[self.height - 1]
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(direction);
+        let (i, j) = self.pixel_index(u, v);
+        self.pixels[j * self.width + i] * self.intensity
+    }
+
+    fn is_sampleable(&self) -> bool {
+        self.total_weight() > 0.0
+    }
+
+    fn sample(&self) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let j = search_cdf(&self.marginal_cdf, rng.gen());
+        let row = &self.conditional_cdf[j * self.width..(j + 1) * self.width];
+        let i = search_cdf(row, rng.gen());
+        let u = (i as f64 + rng.gen::<f64>()) / self.width as f64;
+        let v = (j as f64 + rng.gen::<f64>()) / self.height as f64;
+        self.uv_to_direction(u, v)
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (i, j) = self.pixel_index(u, v);
+        let row_sin = (PI * (j as f64 + 0.5) / self.height as f64).sin();
+        let weight = luminance(self.pixels[j * self.width + i]).max(0.0) * row_sin;
+        // Density over the map, then the Jacobian of the equirectangular mapping.
+        let pdf_uv = weight / self.total_weight() * (self.width * self.height) as f64;
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_pdf() {
+        let (width, height) = (16, 8);
+        let pixels = (0..width * height)
+            .map(|k| Vec3::one() * (1.0 + (k % 7) as f64))
+            .collect();
+        let map = EnvironmentMap::from_pixels(width, height, pixels)
+            .unwrap()
+            .with_rotation(40.0);
+
+        // The density integrates to one over the sphere.
+        let n = 400;
+        let mut integral = 0.0;
+        for a in 0..n {
+            for b in 0..n {
+                let u = (a as f64 + 0.5) / n as f64;
+                let v = (b as f64 + 0.5) / n as f64;
+                let d_omega = 2.0 * PI * PI * (PI * v).sin() / (n * n) as f64;
+                integral += map.pdf(map.uv_to_direction(u, v)) * d_omega;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-2);
+
+        let d = map.sample();
+        assert!(map.pdf(d) > 0.0);
+        let (u, v) = map.direction_to_uv(map.uv_to_direction(0.3, 0.6));
+        assert!((u - 0.3).abs() < 1e-9 && (v - 0.6).abs() < 1e-9);
+    }
+    #[test]
+    fn test_invalid_environment_maps() {
+        assert!(EnvironmentMap::new(Path::new("missing.hdr")).is_err());
+        assert!(EnvironmentMap::from_pixels(0, 0, vec![]).is_err());
+        assert!(EnvironmentMap::from_pixels(2, 1, vec![Vec3::one()]).is_err());
+        assert!(EnvironmentMap::from_fn(0, 8, |d| d).is_err());
+    }
+}
@@ -0,0 +1,514 @@
+//! Bidirectional path tracing: subpaths started from the camera and from the
+//! lights are joined vertex by vertex, and every way of building the same
+//! path is weighted against the others with the power heuristic.
+use crate::camera::Camera;
+use crate::hittable::HitRecord;
+use crate::integrator::{
+    background, continue_path, environment_light, punctual_light, Bounce, Integrator, PathState,
+    RenderStats, SplatBuffer,
+};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::luminance;
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Emission of the light at `rec` towards `direction`, from whichever side
+/// that is.
+fn emitted_towards(rec: &HitRecord, direction: Vec3) -> Vec3 {
+    if Vec3::dot(direction, rec.normal) >= 0.0 {
+        rec.mat_ptr.emitted(rec)
+    } else {
+        rec.mat_ptr.emitted(&rec.flipped())
+    }
+}
+
+/// Probability of a light subpath leaving the side `rec.normal` faces, in
+/// proportion to how bright each side is.
+fn side_probability(rec: &HitRecord) -> f64 {
+    let this = luminance(rec.mat_ptr.emitted(rec)).max(0.0);
+    let other = luminance(rec.mat_ptr.emitted(&rec.flipped())).max(0.0);
+    if this + other <= 0.0 {
+        return 0.0;
+    }
+    this / (this + other)
+}
+
+/// Solid angle density of a light subpath leaving `rec` along `direction`:
+/// a side picked by `side_probability`, then a cosine weighted direction.
+fn emission_pdf(rec: &HitRecord, direction: Vec3) -> f64 {
+    let cosine = Vec3::dot(Vec3::unit_vector(direction), rec.normal);
+    if cosine >= 0.0 {
+        side_probability(rec) * cosine / PI
+    } else {
+        side_probability(&rec.flipped()) * -cosine / PI
+    }
+}
+
+/// Transmittance from `from` to `to`, or zero if something is in the way.
+/// The connection starts in the media of `r_in`, the ray that reached `from`.
+fn visibility(scene: &Scene, from: Vec3, to: Vec3, r_in: &Ray) -> Vec3 {
+    let d = to - from;
+    let distance = d.length();
+    let mut shadow_ray = Ray::new(from, d / distance, r_in.time);
+    shadow_ray.media = r_in.media;
+    if scene.hit(&shadow_ray, 0.001, distance - 0.001).is_some() {
+        return Vec3::zero();
+    }
+    shadow_ray.media.transmittance(distance)
+}
+
+/// A scattering event on a subpath, or the point a light subpath starts from.
+#[derive(Clone, Copy)]
+struct Vertex<'a> {
+    rec: HitRecord<'a>,
+    /// Ray that reached the vertex, or for a light the ray leaving it.
+    r_in: Ray,
+    /// Weight of the subpath up to here, before the material here applies.
+    beta: Vec3,
+    is_light: bool,
+    /// Left along a specular lobe, so the next vertex could not have been
+    /// found by any other strategy.
+    delta: bool,
+    /// Density of the vertex along its own subpath and from the other end.
+    /// Both are per unit area but leave out the cosine at the vertex itself,
+    /// which cancels wherever they are compared.
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl<'a> Vertex<'a> {
+    fn p(&self) -> Vec3 {
+        self.rec.p
+    }
+
+    /// Light sent towards `direction`, with the cosine there: scattered from
+    /// the previous vertex, or emitted for a light.
+    fn eval(&self, direction: Vec3) -> Vec3 {
+        if self.is_light {
+            let cosine = Vec3::dot(direction, self.rec.normal).abs();
+            return emitted_towards(&self.rec, direction) * cosine;
+        }
+        self.rec.mat_ptr.eval(&self.r_in, &self.rec, direction)
+    }
+
+    /// Solid angle density of leaving towards `direction` after arriving from
+    /// `from`, or from the previous vertex if `None`.
+    fn pdf(&self, from: Option<Vec3>, direction: Vec3) -> f64 {
+        if self.is_light {
+            return emission_pdf(&self.rec, direction);
+        }
+        let r_in = match from {
+            Some(q) => Ray::new(q, Vec3::unit_vector(self.p() - q), self.r_in.time),
+            None => self.r_in,
+        };
+        self.rec.mat_ptr.pdf(&r_in, &self.rec, direction)
+    }
+}
+
+/// Power heuristic weight of the path made of `camera`, the camera subpath
+/// after the lens, and `light`. The vertices already hold the densities the
+/// joined path gives them.
+fn mis_weight(camera: &[Vertex], light: &[Vertex]) -> f64 {
+    // Densities of zero belong to specular vertices, which the delta flags
+    // rule out below.
+    let remap = |pdf: f64| if pdf == 0.0 { 1.0 } else { pdf };
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (0..camera.len()).rev() {
+        ratio *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+        let prev_delta = i > 0 && camera[i - 1].delta;
+        if !camera[i].delta && !prev_delta {
+            sum += ratio * ratio;
+        }
+    }
+    ratio = 1.0;
+    for i in (0..light.len()).rev() {
+        ratio *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+        let prev_delta = i > 0 && light[i - 1].delta;
+        if !light[i].delta && !prev_delta {
+            sum += ratio * ratio;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+/// Bidirectional path tracer. Each sample traces one subpath from the camera
+/// and one from a point on an emissive object, then joins every prefix of one
+/// to every prefix of the other, so light reaching the camera through small
+/// gaps or via glass is found from whichever end is easier. Paths joined
+/// straight to the lens land on other pixels and go to the splat buffer.
+///
+/// Light subpaths start on the objects in `Scene::lights`. Punctual lights,
+/// the background and emissive media are gathered along the camera subpath as
+/// in `PathTracer`. Materials are evaluated with the two directions swapped
+/// along light subpaths, which assumes they scatter the same both ways.
+#[derive(Clone, Copy)]
+pub struct BidirectionalPathTracer {
+    pub camera: Camera,
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+}
+
+impl BidirectionalPathTracer {
+    pub fn new(camera: Camera, max_depth: i32) -> Self {
+        Self {
+            camera,
+            max_depth,
+            roulette_depth: 3,
+        }
+    }
+
+    /// Number of bounces on either subpath before Russian roulette may end it.
+    pub fn with_roulette_depth(mut self, roulette_depth: i32) -> Self {
+        self.roulette_depth = roulette_depth;
+        self
+    }
+
+    /// Follows `r` and appends a vertex to `path` at every hit. `path` starts
+    /// out empty for camera subpaths, which pass `radiance` to also gather the
+    /// light only they can find, and holds the light for light subpaths.
+    fn random_walk<'a>(
+        &self,
+        mut r: Ray,
+        scene: &'a Scene,
+        mut state: PathState,
+        path: &mut Vec<Vertex<'a>>,
+        mut radiance: Option<&mut Vec3>,
+        stats: &mut RenderStats,
+    ) {
+        let mut pdf = match path.last() {
+            Some(light) => light.pdf(None, r.direction),
+            None => self.camera.pdf_direction(r.direction),
+        };
+        while state.depth > 0 {
+            let Some(rec) = scene.hit(&r, 0.001, f64::INFINITY) else {
+                if let Some(radiance) = radiance.as_deref_mut() {
+                    *radiance += state.throughput * background(&r, scene, state.bsdf_pdf);
+                }
+                break;
+            };
+            let distance = rec.t * r.direction.length();
+            let transmittance = r.media.transmittance(distance);
+            let mut vertex = Vertex {
+                rec,
+                r_in: r,
+                beta: state.throughput * transmittance,
+                is_light: false,
+                delta: false,
+                pdf_fwd: pdf / (distance * distance),
+                pdf_rev: 0.0,
+            };
+            if let Some(radiance) = radiance.as_deref_mut() {
+                // Emission of lights is weighted in `connect`.
+                if !rec.mat_ptr.is_emissive() {
+                    *radiance += vertex.beta * rec.mat_ptr.emitted(&rec);
+                }
+            }
+            let Some(mut srec) = rec.mat_ptr.scatter(&r, &rec) else {
+                path.push(vertex);
+                break;
+            };
+            stats.bounces += 1;
+            continue_path(&r, &rec, &mut srec.scattered);
+            if let Some(radiance) = radiance.as_deref_mut() {
+                let direct = punctual_light(&r, &rec, scene) + environment_light(&r, &rec, scene);
+                *radiance += vertex.beta * direct;
+            }
+
+            let direction = Vec3::unit_vector(srec.scattered.direction);
+            let (pdf_next, pdf_back) = if srec.is_specular {
+                vertex.delta = true;
+                (0.0, 0.0)
+            } else {
+                let back = -Vec3::unit_vector(r.direction);
+                (
+                    vertex.pdf(None, direction),
+                    vertex.pdf(Some(rec.p + direction), back),
+                )
+            };
+            if let Some(prev) = path.last_mut() {
+                prev.pdf_rev = pdf_back / (distance * distance);
+            }
+            path.push(vertex);
+
+            let bsdf_pdf = (!srec.is_specular).then_some(pdf_next);
+            match state.scatter(transmittance * srec.attenuation, bsdf_pdf) {
+                Bounce::Continue(next, _) => state = next,
+                Bounce::Absorbed => break,
+                Bounce::Terminated => {
+                    stats.roulette_terminations += 1;
+                    break;
+                }
+            }
+            pdf = pdf_next;
+            r = srec.scattered;
+        }
+    }
+
+    /// Subpath starting at a point on a light picked uniformly, or an empty
+    /// one if the scene has no emissive objects.
+    fn light_subpath<'a>(
+        &self,
+        scene: &'a Scene,
+        time: f64,
+        stats: &mut RenderStats,
+    ) -> Vec<Vertex<'a>> {
+        let mut path = Vec::new();
+        if scene.lights.is_empty() {
+            return path;
+        }
+        let mut rng = rand::thread_rng();
+        let light = scene.lights[rng.gen_range(0..scene.lights.len())];
+        let Some((rec, area_pdf)) = scene.world.hittable_list[light].sample_surface(time) else {
+            return path;
+        };
+        let rec = if rng.gen::<f64>() < side_probability(&rec) {
+            rec
+        } else {
+            rec.flipped()
+        };
+        let emitted = rec.mat_ptr.emitted(&rec);
+        if emitted == Vec3::zero() {
+            return path;
+        }
+
+        let mut direction = rec.normal + Vec3::random_unit_vector();
+        if direction.near_zero() {
+            direction = rec.normal;
+        }
+        let direction = Vec3::unit_vector(direction);
+        let pdf_position = area_pdf / scene.lights.len() as f64;
+        let pdf_direction = emission_pdf(&rec, direction);
+        let r = Ray::new(rec.p, direction, time);
+        path.push(Vertex {
+            rec,
+            r_in: r,
+            beta: Vec3::one() / pdf_position,
+            is_light: true,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        });
+
+        let mut state = PathState::new(self.max_depth, self.roulette_depth);
+        let cosine = Vec3::dot(direction, rec.normal);
+        state.throughput = emitted * (cosine / (pdf_position * pdf_direction));
+        self.random_walk(r, scene, state, &mut path, None, stats);
+        if path.len() > 1 {
+            // Weighted as if picked by light sampling from the next vertex.
+            let v = path[0].p() - path[1].p();
+            path[0].pdf_fwd = scene.light_pdf(path[1].p(), v, time) / v.squared_length();
+        }
+        path
+    }
+
+    /// Weighted contribution of the path joining the lens at `lens_point`,
+    /// the camera vertices `camera` and the light vertices `light`. Paths
+    /// with no camera vertices besides the lens are splatted and return zero.
+    fn connect(
+        &self,
+        scene: &Scene,
+        lens_point: Vec3,
+        camera: &[Vertex],
+        light: &[Vertex],
+        splats: &mut SplatBuffer,
+    ) -> Vec3 {
+        // Vertex counts, including the lens and the point on the light.
+        let (s, t) = (light.len(), camera.len() + 1);
+        if s == 0 {
+            // The camera subpath hit a light by itself.
+            let pt = &camera[t - 2];
+            let emitted = pt.rec.mat_ptr.emitted(&pt.rec);
+            if !pt.rec.mat_ptr.is_emissive() || emitted == Vec3::zero() {
+                return Vec3::zero();
+            }
+            if scene.lights.is_empty() {
+                return pt.beta * emitted;
+            }
+            let prev = if t > 2 { camera[t - 3].p() } else { lens_point };
+            let v = pt.p() - prev;
+            let mut path = camera.to_vec();
+            let time = pt.r_in.time;
+            path[t - 2].pdf_rev = scene.light_pdf(prev, v, time) / v.squared_length();
+            if t > 2 {
+                path[t - 3].pdf_rev = emission_pdf(&pt.rec, -v) / v.squared_length();
+            }
+            return pt.beta * emitted * mis_weight(&path, &[]);
+        }
+
+        let qs = &light[s - 1];
+        if t == 1 {
+            // The light subpath joined straight to the lens.
+            let Some(sample) = self.camera.sample_lens(qs.p()) else {
+                return Vec3::zero();
+            };
+            let d = sample.lens_point - qs.p();
+            let f = qs.eval(Vec3::unit_vector(d));
+            if f == Vec3::zero() || sample.pdf <= 0.0 {
+                return Vec3::zero();
+            }
+            let transmittance = visibility(scene, qs.p(), sample.lens_point, &qs.r_in);
+            if transmittance == Vec3::zero() {
+                return Vec3::zero();
+            }
+            let mut path = light.to_vec();
+            let distance_squared = d.squared_length();
+            path[s - 1].pdf_rev = self.camera.pdf_direction(-d) / distance_squared;
+            path[s - 1].delta = false;
+            if s == 1 {
+                let time = qs.r_in.time;
+                path[0].pdf_fwd = scene.light_pdf(sample.lens_point, -d, time) / distance_squared;
+            } else {
+                let w = light[s - 2].p() - qs.p();
+                path[s - 2].pdf_rev = qs.pdf(Some(sample.lens_point), w) / w.squared_length();
+            }
+            let weight = mis_weight(&[], &path);
+            let value = qs.beta * f * transmittance * (sample.importance / sample.pdf);
+            splats.add(sample.s, sample.t, value * weight);
+            return Vec3::zero();
+        }
+
+        let pt = &camera[t - 2];
+        let d = qs.p() - pt.p();
+        let distance_squared = d.squared_length();
+        let direction = Vec3::unit_vector(d);
+        let f = pt.eval(direction) * qs.eval(-direction);
+        if f == Vec3::zero() {
+            return Vec3::zero();
+        }
+        let transmittance = visibility(scene, pt.p(), qs.p(), &pt.r_in);
+        if transmittance == Vec3::zero() {
+            return Vec3::zero();
+        }
+
+        let mut camera_path = camera.to_vec();
+        camera_path[t - 2].pdf_rev = qs.pdf(None, -direction) / distance_squared;
+        camera_path[t - 2].delta = false;
+        if t > 2 {
+            let w = camera[t - 3].p() - pt.p();
+            camera_path[t - 3].pdf_rev = pt.pdf(Some(qs.p()), w) / w.squared_length();
+        }
+        let mut light_path = light.to_vec();
+        light_path[s - 1].pdf_rev = pt.pdf(None, direction) / distance_squared;
+        light_path[s - 1].delta = false;
+        if s == 1 {
+            let time = qs.r_in.time;
+            light_path[0].pdf_fwd = scene.light_pdf(pt.p(), d, time) / distance_squared;
+        } else {
+            let w = light[s - 2].p() - qs.p();
+            light_path[s - 2].pdf_rev = qs.pdf(Some(pt.p()), w) / w.squared_length();
+        }
+        let weight = mis_weight(&camera_path, &light_path);
+        pt.beta * f * qs.beta * transmittance * (weight / distance_squared)
+    }
+}
+
+impl Integrator for BidirectionalPathTracer {
+    fn radiance(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        splats: &mut SplatBuffer,
+        stats: &mut RenderStats,
+    ) -> Vec3 {
+        stats.paths += 1;
+        let mut radiance = Vec3::zero();
+        let mut camera_path = Vec::new();
+        let state = PathState::new(self.max_depth, self.roulette_depth);
+        self.random_walk(
+            *r,
+            scene,
+            state,
+            &mut camera_path,
+            Some(&mut radiance),
+            stats,
+        );
+        let mut light_stats = RenderStats::default();
+        let light_path = self.light_subpath(scene, r.time, &mut light_stats);
+        stats.light_paths += 1;
+        stats.light_bounces += light_stats.bounces;
+        stats.light_roulette_terminations += light_stats.roulette_terminations;
+
+        for t in 1..=camera_path.len() + 1 {
+            for s in 0..=light_path.len() {
+                // Both ends count as vertices but not as bounces.
+                if (s == 0 && t == 1) || s + t > self.max_depth as usize + 2 {
+                    continue;
+                }
+                let camera = &camera_path[..t - 1];
+                radiance += self.connect(scene, r.origin, camera, &light_path[..s], splats);
+            }
+        }
+        radiance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::{XyRect, XzRect, YzRect};
+    use crate::hittable_list::HittableList;
+    use crate::integrator::PathTracer;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::texture::SolidColor;
+
+    fn cornell_box() -> Scene {
+        let diffuse = |c: Vec3| Lambertian::new(Box::new(SolidColor::new(c)));
+        let white = || diffuse(Vec3::one() * 0.73);
+        let green = diffuse(Vec3::new(0.12, 0.45, 0.15));
+        let red = diffuse(Vec3::new(0.65, 0.05, 0.05));
+        let light = DiffuseLight::new(Box::new(SolidColor::new(Vec3::one() * 4.0)));
+        let mut world = HittableList::new();
+        world.add(Box::new(YzRect::new(0.0, 5.0, 0.0, 5.0, 5.0, green)));
+        world.add(Box::new(YzRect::new(0.0, 5.0, 0.0, 5.0, 0.0, red)));
+        world.add(Box::new(XzRect::new(1.5, 3.5, 1.5, 3.5, 4.99, light)));
+        world.add(Box::new(XzRect::new(0.0, 5.0, 0.0, 5.0, 0.0, white())));
+        world.add(Box::new(XzRect::new(0.0, 5.0, 0.0, 5.0, 5.0, white())));
+        world.add(Box::new(XyRect::new(0.0, 5.0, 0.0, 5.0, 5.0, white())));
+        Scene::new(world, Vec3::zero())
+    }
+
+    #[test]
+    fn test_matches_path_tracer() {
+        let scene = cornell_box();
+        let from = Vec3::new(2.5, 2.5, -8.0);
+        let at = Vec3::new(2.5, 2.5, 0.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let camera = Camera::new(from, at, up, 40.0, 1.0, 0.0, 10.0, 0.0, 1.0);
+        let path_tracer = PathTracer::new(50);
+        let bidirectional = BidirectionalPathTracer::new(camera, 50);
+
+        // Mean over the film, which gathers the splats in its single pixel.
+        let n = 100000;
+        let film_mean = |integrator: &dyn Integrator, stats: &mut RenderStats| {
+            let mut splats = SplatBuffer::new(2, 2);
+            let mut sum = Vec3::zero();
+            for _ in 0..n {
+                let r = camera.get_ray(rand::random(), rand::random());
+                sum += integrator.radiance(&r, &scene, &mut splats, stats);
+            }
+            (sum + splats.get(0, 0)) / n as f64
+        };
+        let mut stats = RenderStats::default();
+        let expected = film_mean(&path_tracer, &mut stats);
+        assert_eq!(stats.light_paths, 0);
+        let mut stats = RenderStats::default();
+        let mean = film_mean(&bidirectional, &mut stats);
+        let channels = [
+            (mean.x(), expected.x()),
+            (mean.y(), expected.y()),
+            (mean.z(), expected.z()),
+        ];
+        for (a, b) in channels {
+            assert!((a / b - 1.0).abs() < 0.05, "{} against {}", a, b);
+        }
+
+        // Light subpaths are counted apart from the camera paths.
+        assert_eq!(stats.paths, n);
+        assert_eq!(stats.light_paths, n);
+        assert!(stats.light_bounces > 0);
+        assert!(stats.mean_path_length() < 10.0);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::aabb::Aabb;
 use crate::aarect::{XyRect, XzRect, YzRect};
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{FlipFace, HitRecord, Hittable};
 use crate::hittable_list::HittableList;
 use crate::material::Material;
 use crate::ray::Ray;
@@ -20,14 +20,15 @@ impl<M: Material + 'static> Box_<M> {
 
         let mut sides = HittableList::new();
 
-        sides.add(Box::new(XyRect::new(
+        // The faces on the minimum side point outwards along the negative axis
+        sides.add(Box::new(FlipFace::new(Box::new(XyRect::new(
             p0.x(),
             p1.x(),
             p0.y(),
             p1.y(),
             p0.z(),
             mp0,
-        )));
+        )))));
 
         sides.add(Box::new(XyRect::new(
             p0.x(),
@@ -38,14 +39,14 @@ impl<M: Material + 'static> Box_<M> {
             mp1,
         )));
 
-        sides.add(Box::new(XzRect::new(
+        sides.add(Box::new(FlipFace::new(Box::new(XzRect::new(
             p0.x(),
             p1.x(),
             p0.z(),
             p1.z(),
             p0.y(),
             mp2,
-        )));
+        )))));
 
         sides.add(Box::new(XzRect::new(
             p0.x(),
@@ -56,14 +57,14 @@ impl<M: Material + 'static> Box_<M> {
             mp3,
         )));
 
-        sides.add(Box::new(YzRect::new(
+        sides.add(Box::new(FlipFace::new(Box::new(YzRect::new(
             p0.y(),
             p1.y(),
             p0.z(),
             p1.z(),
             p0.x(),
             mp4,
-        )));
+        )))));
 
         sides.add(Box::new(YzRect::new(
             p0.y(),
@@ -84,7 +85,7 @@ impl<M: Material + 'static> Box_<M> {
 }
 
 impl<M: Material> Hittable for Box_<M> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         self.sides.hit(r, t_min, t_max)
     }
 
@@ -92,4 +93,28 @@ impl<M: Material> Hittable for Box_<M> {
         let output_box = Aabb::new(self.box_min, self.box_max);
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        self.sides.pdf_value(origin, v, time)
+    }
+
+    fn random(&self, origin: Vec3, time: f64) -> Vec3 {
+        self.sides.random(origin, time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.sides.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        self.sides.sample_surface(time)
+    }
+
+    fn area(&self) -> Option<f64> {
+        self.sides.area()
+    }
+
+    fn resolve_emitting_area(&self) {
+        self.sides.resolve_emitting_area();
+    }
 }
@@ -1,6 +1,20 @@
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 use rand::Rng;
+use std::f64::consts::PI;
+
+/// A point on the lens connected to a point in the scene, for light paths
+/// that end at the camera.
+pub struct CameraSample {
+    pub lens_point: Vec3,
+    /// Film coordinates that `get_ray` maps to the ray towards the scene point.
+    pub s: f64,
+    pub t: f64,
+    /// Importance `We` carried by that ray.
+    pub importance: f64,
+    /// Solid angle density, seen from the scene point, of picking `lens_point`.
+    pub pdf: f64,
+}
 
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub struct Camera {
@@ -71,4 +85,113 @@ impl Camera {
             rng.gen_range(self.time0..self.time1),
         )
     }
+
+    /// Distance from the lens to the plane in focus, which `get_ray` aims at.
+    fn focus_distance(&self) -> f64 {
+        Vec3::dot(self.origin - self.lower_left_corner, self.w)
+    }
+
+    /// Density per unit lens area, or one for a pinhole.
+    fn lens_pdf(&self) -> f64 {
+        if self.lens_radius > 0.0 {
+            1.0 / (PI * self.lens_radius * self.lens_radius)
+        } else {
+            1.0
+        }
+    }
+
+    /// Solid angle density of `get_ray` picking `direction` when the film
+    /// coordinates are spread uniformly over the unit square, from any point
+    /// on the lens.
+    pub fn pdf_direction(&self, direction: Vec3) -> f64 {
+        let cos_theta = -Vec3::dot(Vec3::unit_vector(direction), self.w);
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        let focus = self.focus_distance();
+        // Area of the film moved to unit distance from the lens.
+        let film_area = self.horizontal.length() * self.vertical.length() / (focus * focus);
+        1.0 / (film_area * cos_theta.powi(3))
+    }
+
+    /// Importance `We` of the ray leaving the lens along `direction`. It is
+    /// normalized so that its integral over the lens and all directions onto
+    /// the unit square of the film, with the cosine at the lens, is one.
+    pub fn importance(&self, direction: Vec3) -> f64 {
+        let cos_theta = -Vec3::dot(Vec3::unit_vector(direction), self.w);
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        self.pdf_direction(direction) * self.lens_pdf() / cos_theta
+    }
+
+    /// Film coordinates `(s, t)` that `get_ray` maps to the ray from
+    /// `lens_point` along `direction`, or `None` if it points away from the
+    /// film. They may lie outside the unit square.
+    pub fn film_coordinates(&self, lens_point: Vec3, direction: Vec3) -> Option<(f64, f64)> {
+        let denominator = Vec3::dot(direction, self.w);
+        if denominator >= 0.0 {
+            return None;
+        }
+        let t = Vec3::dot(self.lower_left_corner - lens_point, self.w) / denominator;
+        let q = lens_point + t * direction - self.lower_left_corner;
+        Some((
+            Vec3::dot(q, self.horizontal) / self.horizontal.squared_length(),
+            Vec3::dot(q, self.vertical) / self.vertical.squared_length(),
+        ))
+    }
+
+    /// Picks a point on the lens to connect to `p`.
+    pub fn sample_lens(&self, p: Vec3) -> Option<CameraSample> {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+        let lens_point = self.origin + self.u * rd.x() + self.v * rd.y();
+        let direction = p - lens_point;
+        let (s, t) = self.film_coordinates(lens_point, direction)?;
+        let distance_squared = direction.squared_length();
+        let cos_theta = -Vec3::dot(direction, self.w) / distance_squared.sqrt();
+        Some(CameraSample {
+            lens_point,
+            s,
+            t,
+            importance: self.importance(direction),
+            pdf: distance_squared * self.lens_pdf() / cos_theta,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_camera_importance() {
+        let camera = Camera::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.5,
+            0.2,
+            4.0,
+            0.0,
+            1.0,
+        );
+        let r = camera.get_ray(0.3, 0.8);
+        let (s, t) = camera.film_coordinates(r.origin, r.direction).unwrap();
+        assert!((s - 0.3).abs() < 1e-9 && (t - 0.8).abs() < 1e-9);
+
+        // The directions onto the film take up all of the density.
+        let n = 200_000;
+        let mut total = 0.0;
+        for _ in 0..n {
+            let direction = Vec3::random_unit_vector();
+            let Some((s, t)) = camera.film_coordinates(camera.origin, direction) else {
+                continue;
+            };
+            if (0.0..1.0).contains(&s) && (0.0..1.0).contains(&t) {
+                total += camera.pdf_direction(direction) * 4.0 * PI / n as f64;
+            }
+        }
+        assert!((total - 1.0).abs() < 0.05);
+    }
 }
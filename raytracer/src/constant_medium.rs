@@ -2,12 +2,83 @@ use crate::aabb::Aabb;
 use crate::aarect::{XyRect, XzRect, YzRect};
 use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
-use crate::material::Material;
+use crate::material::{Material, Volumetric};
+use crate::phase::PhaseFunction;
 use crate::ray::Ray;
 use crate::texture::Texture;
 use crate::vec3::Vec3;
 use rand::Rng;
 
+/// Upper bound on the boundary crossings followed along one ray.
+const MAX_CROSSINGS: usize = 64;
+
+/// Part of a ray inside a medium boundary, with the texture coordinates of the
+/// boundary crossing that bounds it.
+pub struct Interval {
+    pub t0: f64,
+    pub t1: f64,
+    pub u: f64,
+    pub v: f64,
+}
+
+/// Pieces of `r` within `[t_min, t_max]` that lie inside `boundary`.
+///
+/// Every crossing along the whole line is visited, and entering or leaving is
+/// decided by `front_face`, so non-convex boundaries give several intervals.
+/// A ray that first crosses a back face starts inside, which covers cameras
+/// placed in the medium as well as open boundaries.
+pub fn inside_intervals(boundary: &dyn Hittable, r: Ray, t_min: f64, t_max: f64) -> Vec<Interval> {
+    let mut intervals = Vec::new();
+    let mut entry: Option<(f64, f64, f64)> = None;
+    let mut inside = false;
+    let mut t = -f64::INFINITY;
+    for crossing in 0..MAX_CROSSINGS {
+        let Some(rec) = boundary.hit(r, t, f64::INFINITY) else {
+            break;
+        };
+        t = rec.t + 0.0001;
+        if rec.front_face {
+            if !inside {
+                entry = Some((rec.t, rec.u, rec.v));
+            }
+            inside = true;
+        } else {
+            if crossing == 0 {
+                entry = Some((-f64::INFINITY, rec.u, rec.v));
+            }
+            if let Some((t0, u, v)) = entry.take() {
+                intervals.push(Interval {
+                    t0,
+                    t1: rec.t,
+                    u,
+                    v,
+                });
+            }
+            inside = false;
+        }
+        if rec.t > t_max {
+            break;
+        }
+    }
+    if let Some((t0, u, v)) = entry {
+        // The boundary is open on this side, so the medium extends forever.
+        intervals.push(Interval {
+            t0,
+            t1: f64::INFINITY,
+            u,
+            v,
+        });
+    }
+    intervals
+        .into_iter()
+        .filter_map(|i| {
+            let t0 = i.t0.max(t_min);
+            let t1 = i.t1.min(t_max);
+            (t0 < t1).then_some(Interval { t0, t1, ..i })
+        })
+        .collect()
+}
+
 pub struct ConstantMedium {
     neg_inv_density: f64,
     boundary: Box<dyn Hittable>,
@@ -22,46 +93,78 @@ impl ConstantMedium {
             phase_function: a,
         }
     }
+
+    /// A medium scattering with `phase`, e.g. a forward scattering fog.
+    pub fn with_phase(
+        d: f64,
+        b: Box<dyn Hittable>,
+        albedo: Box<dyn Texture>,
+        phase: Box<dyn PhaseFunction>,
+    ) -> Self {
+        Self::new(d, b, Box::new(Volumetric::new(albedo, phase)))
+    }
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if let Some(mut rec1) = self.boundary.hit(r, -f64::INFINITY, f64::INFINITY) {
-            if let Some(mut rec2) = self.boundary.hit(r, rec1.t + 0.0001, f64::INFINITY) {
-                if rec1.t < t_min {
-                    rec1.t = t_min;
-                }
-                if rec2.t > t_max {
-                    rec2.t = t_max;
-                }
-                if rec1.t >= rec2.t {
-                    return None;
-                }
-                if rec1.t < 0.0 {
-                    rec1.t = 0.0;
-                }
-                let ray_length = r.direction.length();
-                let distance_inside_boundary = (rec2.t - rec1.t) * r.direction.length();
-                let mut rng = rand::thread_rng();
-                let hit_distance = self.neg_inv_density * f64::log2(rng.gen::<f64>());
-                if hit_distance > distance_inside_boundary {
-                    return None;
-                }
-                let t = rec1.t + hit_distance / ray_length;
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let ray_length = r.direction.length();
+        let mut rng = rand::thread_rng();
+        // The medium is homogeneous, so one free flight distance can be spent
+        // across all the pieces of the ray that lie inside the boundary.
+        let mut hit_distance = self.neg_inv_density * f64::ln(rng.gen::<f64>());
+        for interval in inside_intervals(&*self.boundary, r, t_min, t_max) {
+            let distance_inside_boundary = (interval.t1 - interval.t0) * ray_length;
+            if hit_distance <= distance_inside_boundary {
+                let t = interval.t0 + hit_distance / ray_length;
                 let p = r.at(t);
                 let normal = Vec3::new(1.0, 0.0, 0.0);
-                let hit_record =
-                    HitRecord::new(p, t, rec1.u, rec1.v, normal, r, &(*self.phase_function));
-                Some(hit_record)
-            } else {
-                return None;
+                let hit_record = HitRecord::new(
+                    p,
+                    t,
+                    interval.u,
+                    interval.v,
+                    normal,
+                    r,
+                    &(*self.phase_function),
+                );
+                return Some(hit_record);
             }
-        } else {
-            return None;
+            hit_distance -= distance_inside_boundary;
         }
+        None
     }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         self.boundary.bounding_box(time0, time1)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+
+    fn ball(x: f64) -> Box<dyn Hittable> {
+        let material = Lambertian::new(Box::new(SolidColor::new(Vec3::one())));
+        Box::new(Sphere::new(Vec3::new(x, 0.0, 0.0), 1.0, material))
+    }
+
+    #[test]
+    fn test_inside_intervals() {
+        let mut pair = HittableList::new();
+        pair.add(ball(0.0));
+        pair.add(ball(4.0));
+        let r = Ray::new(Vec3::new(-2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let intervals = inside_intervals(&pair, r, 0.001, f64::INFINITY);
+        let ts: Vec<_> = intervals.iter().map(|i| (i.t0, i.t1)).collect();
+        assert_eq!(ts, vec![(1.0, 3.0), (5.0, 7.0)]);
+
+        // Starting inside the first ball.
+        let r = Ray::new(Vec3::zero(), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let intervals = inside_intervals(&pair, r, 0.001, 4.0);
+        let ts: Vec<_> = intervals.iter().map(|i| (i.t0, i.t1)).collect();
+        assert_eq!(ts, vec![(0.001, 1.0), (3.0, 4.0)]);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::aabb::Aabb;
+use crate::constant_medium::inside_intervals;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use rand::Rng;
+
+/// Participating medium whose density varies inside `boundary`, e.g. clouds,
+/// smoke or god-ray fog. The density is the first channel of `density` at the
+/// sample point times `scale`, and has to stay below `majorant`. Densities
+/// above it are clamped to it, since delta tracking cannot produce them.
+///
+/// Free flights are sampled by delta tracking against the majorant: tentative
+/// collisions are drawn as in a homogeneous medium of density `majorant` and
+/// accepted as real with probability `density / majorant`.
+pub struct HeterogeneousMedium {
+    boundary: Box<dyn Hittable>,
+    density: Box<dyn Texture>,
+    scale: f64,
+    majorant: f64,
+    phase_function: Box<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        density: Box<dyn Texture>,
+        scale: f64,
+        majorant: f64,
+        phase_function: Box<dyn Material>,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            scale,
+            majorant,
+            phase_function,
+        }
+    }
+
+    fn density_at(&self, p: &Vec3) -> f64 {
+        (self.density.value(0.0, 0.0, p).x() * self.scale).clamp(0.0, self.majorant)
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let step = self.majorant * r.direction.length();
+        let mut rng = rand::thread_rng();
+        for interval in inside_intervals(&*self.boundary, r, t_min, t_max) {
+            let mut t = interval.t0;
+            loop {
+                t -= f64::ln(1.0 - rng.gen::<f64>()) / step;
+                if t >= interval.t1 {
+                    break;
+                }
+                let p = r.at(t);
+                if rng.gen::<f64>() * self.majorant < self.density_at(&p) {
+                    let normal = Vec3::new(1.0, 0.0, 0.0);
+                    let (u, v) = (interval.u, interval.v);
+                    let hit_record = HitRecord::new(p, t, u, v, normal, r, &*self.phase_function);
+                    return Some(hit_record);
+                }
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Isotropic;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+
+    /// Density rising linearly from 0 at `x = -1` to 2 at `x = 1`.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+            Vec3::one() * (p.x() + 1.0)
+        }
+    }
+
+    fn transmittance(majorant: f64) -> f64 {
+        let white = || Box::new(SolidColor::new(Vec3::one()));
+        let ball = Box::new(Sphere::new(Vec3::zero(), 1.0, Isotropic::new(white())));
+        let phase = Box::new(Isotropic::new(white()));
+        let medium = HeterogeneousMedium::new(ball, Box::new(Ramp), 1.0, majorant, phase);
+        let r = Ray::new(Vec3::new(-2.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let n = 20000;
+        let passed = (0..n)
+            .filter(|_| medium.hit(r, 0.001, f64::INFINITY).is_none())
+            .count();
+        passed as f64 / n as f64
+    }
+
+    #[test]
+    fn test_free_flights_follow_optical_depth() {
+        // The ramp integrates to 2 along the diameter.
+        assert!((transmittance(2.0) - f64::exp(-2.0)).abs() < 0.01);
+        assert!((transmittance(8.0) - f64::exp(-2.0)).abs() < 0.01);
+        // Clamped at 1 the right half has unit density, leaving 0.5 + 1.
+        assert!((transmittance(1.0) - f64::exp(-1.5)).abs() < 0.01);
+    }
+}
@@ -1,8 +1,11 @@
 use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::texture::Texture;
 use crate::vec3::Vec3;
+use rand::Rng;
 
+#[derive(Clone, Copy)]
 pub struct HitRecord<'a> {
     pub p: Vec3,
     pub normal: Vec3,
@@ -40,12 +43,194 @@ impl<'a> HitRecord<'a> {
             mat_ptr,
         }
     }
+
+    /// Record of a point sampled on a surface rather than hit by a ray, seen
+    /// from the side `outward_normal` points to.
+    pub fn on_surface(
+        p: Vec3,
+        u: f64,
+        v: f64,
+        outward_normal: Vec3,
+        time: f64,
+        mat_ptr: &'a dyn Material,
+    ) -> Self {
+        let r = Ray::new(p + outward_normal, -outward_normal, time);
+        Self::new(p, 0.0, u, v, outward_normal, r, mat_ptr)
+    }
+
+    /// The same point seen from the other side of the surface.
+    pub fn flipped(&self) -> Self {
+        Self {
+            normal: -self.normal,
+            front_face: !self.front_face,
+            ..*self
+        }
+    }
 }
 
-pub trait Hittable {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+pub trait Hittable: Send + Sync {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    /// Solid angle density with which `random` picks direction `v` from
+    /// `origin` at `time`.
+    fn pdf_value(&self, _origin: Vec3, _v: Vec3, _time: f64) -> f64 {
+        0.0
+    }
+
+    /// Direction from `origin` to a random point on the object at `time`.
+    fn random(&self, _origin: Vec3, _time: f64) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    /// Whether the object is a light source that `random` can sample.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Point picked uniformly on the surface at `time`, as seen from outside,
+    /// and the density per unit area it was picked with. Light paths start
+    /// from these.
+    fn sample_surface(&self, _time: f64) -> Option<(HitRecord<'_>, f64)> {
+        None
+    }
+
+    /// Surface area, for lights that spread a total power over it. `None` for
+    /// objects that cannot tell, such as media.
+    fn area(&self) -> Option<f64> {
+        None
+    }
+
+    /// Tells the light material of each surface in the object the area of
+    /// that surface, see `Material::set_emitting_area`.
+    fn resolve_emitting_area(&self) {
+        if let (Some(area), Some((rec, _))) = (self.area(), self.sample_surface(0.0)) {
+            rec.mat_ptr.set_emitting_area(area);
+        }
+    }
+}
+
+/// Reports the opposite side of `ptr` as its front face.
+pub struct FlipFace {
+    pub ptr: Box<dyn Hittable>,
+}
+
+impl FlipFace {
+    pub fn new(ptr: Box<dyn Hittable>) -> Self {
+        Self { ptr }
+    }
+}
+
+impl Hittable for FlipFace {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rec = self.ptr.hit(r, t_min, t_max)?;
+        rec.front_face = !rec.front_face;
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.ptr.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        self.ptr.pdf_value(origin, v, time)
+    }
+
+    fn random(&self, origin: Vec3, time: f64) -> Vec3 {
+        self.ptr.random(origin, time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.ptr.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let (mut rec, pdf) = self.ptr.sample_surface(time)?;
+        rec.front_face = !rec.front_face;
+        Some((rec, pdf))
+    }
+
+    fn area(&self) -> Option<f64> {
+        self.ptr.area()
+    }
+
+    fn resolve_emitting_area(&self) {
+        self.ptr.resolve_emitting_area();
+    }
+}
+
+/// Cuts holes into `ptr` using `Texture::alpha` of `opacity`, e.g. the alpha
+/// channel of a PNG `ImageTexture` for leaves, fences and decals. Hits with an
+/// opacity below `threshold` are rejected with probability `1 - opacity` and
+/// the ray carries on, so shadow rays see the same cutouts as camera rays.
+pub struct AlphaCutout {
+    pub ptr: Box<dyn Hittable>,
+    pub opacity: Box<dyn Texture>,
+    pub threshold: f64,
+}
+
+impl AlphaCutout {
+    pub fn new(ptr: Box<dyn Hittable>, opacity: Box<dyn Texture>, threshold: f64) -> Self {
+        Self {
+            ptr,
+            opacity,
+            threshold,
+        }
+    }
+}
+
+impl Hittable for AlphaCutout {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rng = rand::thread_rng();
+        let mut t_min = t_min;
+        loop {
+            let rec = self.ptr.hit(r, t_min, t_max)?;
+            let alpha = self.opacity.alpha(rec.u, rec.v, &rec.p);
+            if alpha >= self.threshold || rng.gen::<f64>() < alpha {
+                return Some(rec);
+            }
+            t_min = rec.t + 0.0001;
+        }
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.ptr.bounding_box(time0, time1)
+    }
+
+    // Lights are sampled over their full shape; directions that end up in a
+    // cutout simply hit whatever lies behind it.
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        self.ptr.pdf_value(origin, v, time)
+    }
+
+    fn random(&self, origin: Vec3, time: f64) -> Vec3 {
+        self.ptr.random(origin, time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.ptr.is_emissive()
+    }
+
+    /// Points are rejected like hits are, returning `None`, so light paths
+    /// leave the cutout in proportion to its opacity.
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let (rec, pdf) = self.ptr.sample_surface(time)?;
+        let alpha = self.opacity.alpha(rec.u, rec.v, &rec.p);
+        if alpha >= self.threshold || rand::thread_rng().gen::<f64>() < alpha {
+            Some((rec, pdf))
+        } else {
+            None
+        }
+    }
+
+    fn area(&self) -> Option<f64> {
+        self.ptr.area()
+    }
+
+    fn resolve_emitting_area(&self) {
+        self.ptr.resolve_emitting_area();
+    }
 }
 
 pub struct Translate {
@@ -60,10 +245,10 @@ impl Translate {
 }
 
 impl Hittable for Translate {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let moved_r = Ray::new(r.origin - self.offset, r.direction, r.time);
         if let Some(rec) = self.ptr.hit(moved_r, t_min, t_max) {
-            let hit_record = HitRecord::new(
+            let mut hit_record = HitRecord::new(
                 rec.p + self.offset,
                 rec.t,
                 rec.u,
@@ -72,6 +257,8 @@ impl Hittable for Translate {
                 moved_r,
                 rec.mat_ptr,
             );
+            // `rec.normal` already faces the ray, so keep the original side.
+            hit_record.front_face = rec.front_face;
             Some(hit_record)
         } else {
             return None;
@@ -89,6 +276,32 @@ impl Hittable for Translate {
             return None;
         }
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        self.ptr.pdf_value(origin - self.offset, v, time)
+    }
+
+    fn random(&self, origin: Vec3, time: f64) -> Vec3 {
+        self.ptr.random(origin - self.offset, time)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.ptr.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let (mut rec, pdf) = self.ptr.sample_surface(time)?;
+        rec.p += self.offset;
+        Some((rec, pdf))
+    }
+
+    fn area(&self) -> Option<f64> {
+        self.ptr.area()
+    }
+
+    fn resolve_emitting_area(&self) {
+        self.ptr.resolve_emitting_area();
+    }
 }
 
 pub struct RotateY {
@@ -103,7 +316,9 @@ impl RotateY {
         let radians = angle * std::f64::consts::PI / 180.0;
         let sin_theta = f64::sin(radians);
         let cos_theta = f64::cos(radians);
-        let Some(bbox) = ptr.bounding_box(0.0, 1.0) else { panic!("Wrong!") };
+        let Some(bbox) = ptr.bounding_box(0.0, 1.0) else {
+            panic!("Wrong!")
+        };
         let mut min = [f64::INFINITY; 3];
         let mut max = [-f64::INFINITY; 3];
         for i in 0..2 {
@@ -135,33 +350,35 @@ impl RotateY {
             ptr,
         }
     }
+
+    /// Takes a world space vector into the object space of `ptr`.
+    fn to_object(&self, a: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * a.x() - self.sin_theta * a.z(),
+            a.y(),
+            self.sin_theta * a.x() + self.cos_theta * a.z(),
+        )
+    }
+
+    fn to_world(&self, a: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * a.x() + self.sin_theta * a.z(),
+            a.y(),
+            -self.sin_theta * a.x() + self.cos_theta * a.z(),
+        )
+    }
 }
 
 impl Hittable for RotateY {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let origin = Vec3::new(
-            self.cos_theta * r.origin.x() - self.sin_theta * r.origin.z(),
-            r.origin.y(),
-            self.sin_theta * r.origin.x() + self.cos_theta * r.origin.z(),
-        );
-        let direction = Vec3::new(
-            self.cos_theta * r.direction.x() - self.sin_theta * r.direction.z(),
-            r.direction.y(),
-            self.sin_theta * r.direction.x() + self.cos_theta * r.direction.z(),
-        );
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let origin = self.to_object(r.origin);
+        let direction = self.to_object(r.direction);
         let rotated_r = Ray::new(origin, direction, r.time);
         if let Some(rec) = self.ptr.hit(rotated_r, t_min, t_max) {
-            let p = Vec3::new(
-                self.cos_theta * rec.p.x() + self.sin_theta * rec.p.z(),
-                rec.p.y(),
-                -self.sin_theta * rec.p.x() + self.cos_theta * rec.p.z(),
-            );
-            let normal = Vec3::new(
-                self.cos_theta * rec.normal.x() + self.sin_theta * rec.normal.z(),
-                rec.normal.y(),
-                -self.sin_theta * rec.normal.x() + self.cos_theta * rec.normal.z(),
-            );
-            let hit_record = HitRecord::new(p, rec.t, rec.u, rec.v, normal, r, rec.mat_ptr);
+            let p = self.to_world(rec.p);
+            let normal = self.to_world(rec.normal);
+            let mut hit_record = HitRecord::new(p, rec.t, rec.u, rec.v, normal, r, rec.mat_ptr);
+            hit_record.front_face = rec.front_face;
             Some(hit_record)
         } else {
             return None;
@@ -172,4 +389,101 @@ impl Hittable for RotateY {
         let output_box = Aabb::new(self.bbox.minimum, self.bbox.maximum);
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        self.ptr
+            .pdf_value(self.to_object(origin), self.to_object(v), time)
+    }
+
+    fn random(&self, origin: Vec3, time: f64) -> Vec3 {
+        self.to_world(self.ptr.random(self.to_object(origin), time))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.ptr.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let (mut rec, pdf) = self.ptr.sample_surface(time)?;
+        rec.p = self.to_world(rec.p);
+        rec.normal = self.to_world(rec.normal);
+        Some((rec, pdf))
+    }
+
+    fn area(&self) -> Option<f64> {
+        self.ptr.area()
+    }
+
+    fn resolve_emitting_area(&self) {
+        self.ptr.resolve_emitting_area();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XyRect;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::texture::SolidColor;
+
+    /// Clear on the left half, with an opacity of 0.3 on the right half.
+    struct HalfClear;
+
+    impl Texture for HalfClear {
+        fn value(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+            Vec3::one()
+        }
+
+        fn alpha(&self, u: f64, _v: f64, _p: &Vec3) -> f64 {
+            if u < 0.5 {
+                0.0
+            } else {
+                0.3
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_cutout_lets_rays_through() {
+        let square = || {
+            let material = Lambertian::new(Box::new(SolidColor::new(Vec3::one())));
+            Box::new(XyRect::new(0.0, 1.0, 0.0, 1.0, 0.0, material))
+        };
+        let cutout = AlphaCutout::new(square(), Box::new(HalfClear), 1.0);
+        let direction = Vec3::new(0.0, 0.0, -1.0);
+        let clear = Ray::new(Vec3::new(0.25, 0.5, 1.0), direction, 0.0);
+        let translucent = Ray::new(Vec3::new(0.75, 0.5, 1.0), direction, 0.0);
+        let n = 20000;
+        let mut hits = 0;
+        for _ in 0..n {
+            assert!(cutout.hit(clear, 0.001, f64::INFINITY).is_none());
+            if cutout.hit(translucent, 0.001, f64::INFINITY).is_some() {
+                hits += 1;
+            }
+        }
+        let fraction = hits as f64 / n as f64;
+        assert!((fraction - 0.3).abs() < 0.02, "{}", fraction);
+
+        // Opacities at or above the threshold are solid.
+        let solid = AlphaCutout::new(square(), Box::new(HalfClear), 0.3);
+        assert!((0..100).all(|_| solid.hit(translucent, 0.001, f64::INFINITY).is_some()));
+    }
+
+    #[test]
+    fn test_alpha_cutout_emits_from_opaque_parts() {
+        let light = DiffuseLight::new(Box::new(SolidColor::new(Vec3::one())));
+        let square = Box::new(XyRect::new(0.0, 1.0, 0.0, 1.0, 0.0, light));
+        let cutout = AlphaCutout::new(square, Box::new(HalfClear), 1.0);
+        let n = 20000;
+        let mut samples = 0;
+        for _ in 0..n {
+            if let Some((rec, _)) = cutout.sample_surface(0.0) {
+                assert!(rec.p.x() >= 0.5);
+                samples += 1;
+            }
+        }
+        // Half the square at an opacity of 0.3
+        let fraction = samples as f64 / n as f64;
+        assert!((fraction - 0.15).abs() < 0.02, "{}", fraction);
+    }
 }
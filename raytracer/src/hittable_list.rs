@@ -1,6 +1,8 @@
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
+use crate::vec3::Vec3;
+use rand::Rng;
 use std::vec::Vec;
 
 pub struct HittableList {
@@ -21,10 +23,31 @@ impl HittableList {
     pub fn clear(&mut self) {
         self.hittable_list.clear();
     }
+
+    /// Members `random` and `sample_surface` pick from: the lights if there
+    /// are any, so a lamp grouped with its fittings is sampled only where it
+    /// emits, and all of them otherwise.
+    fn sampled(&self) -> impl Iterator<Item = &dyn Hittable> {
+        let lights_only = self.is_emissive();
+        self.hittable_list
+            .iter()
+            .map(|obj| &**obj)
+            .filter(move |obj| !lights_only || obj.is_emissive())
+    }
+
+    /// Picks one of the `sampled` members uniformly, with how many there are.
+    fn pick(&self) -> Option<(&dyn Hittable, usize)> {
+        let count = self.sampled().count();
+        if count == 0 {
+            return None;
+        }
+        let index = rand::thread_rng().gen_range(0..count);
+        Some((self.sampled().nth(index)?, count))
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut hit_anything: Option<HitRecord> = None;
         let mut closet_so_far = t_max;
 
@@ -38,6 +61,48 @@ impl Hittable for HittableList {
         hit_anything
     }
 
+    /// Average over the `sampled` members, matching `random` picking one
+    /// uniformly.
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        let (sum, count) = self.sampled().fold((0.0, 0), |(sum, count), obj| {
+            (sum + obj.pdf_value(origin, v, time), count + 1)
+        });
+        if count == 0 {
+            return 0.0;
+        }
+        sum / count as f64
+    }
+
+    fn random(&self, origin: Vec3, time: f64) -> Vec3 {
+        match self.pick() {
+            Some((obj, _)) => obj.random(origin, time),
+            None => Vec3::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.hittable_list.iter().any(|obj| obj.is_emissive())
+    }
+
+    /// Picks a member like `random`.
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let (obj, count) = self.pick()?;
+        let (rec, pdf) = obj.sample_surface(time)?;
+        Some((rec, pdf / count as f64))
+    }
+
+    /// Total area of the members, if all of them have one.
+    fn area(&self) -> Option<f64> {
+        self.hittable_list.iter().map(|obj| obj.area()).sum()
+    }
+
+    /// Each member is a surface of its own.
+    fn resolve_emitting_area(&self) {
+        for obj in self.hittable_list.iter() {
+            obj.resolve_emitting_area();
+        }
+    }
+
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         if self.hittable_list.is_empty() {
             return None;
@@ -0,0 +1,578 @@
+//! Estimators of the radiance arriving at the camera, and the light sampling
+//! they share.
+use crate::hittable::HitRecord;
+use crate::material::ScatterRecord;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{SampledSpectrum, SampledWavelengths};
+use crate::vec3::Vec3;
+use rand::Rng;
+
+/// Counters gathered while rendering, summed over threads at the end.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderStats {
+    pub paths: u64,
+    /// Scattering events over all paths.
+    pub bounces: u64,
+    /// Paths ended by Russian roulette rather than by escaping, being
+    /// absorbed or reaching the depth limit.
+    pub roulette_terminations: u64,
+    /// The same for the light subpaths of `BidirectionalPathTracer`, kept
+    /// apart from the camera paths above.
+    pub light_paths: u64,
+    pub light_bounces: u64,
+    pub light_roulette_terminations: u64,
+}
+
+impl RenderStats {
+    pub fn merge(&mut self, other: &RenderStats) {
+        self.paths += other.paths;
+        self.bounces += other.bounces;
+        self.roulette_terminations += other.roulette_terminations;
+        self.light_paths += other.light_paths;
+        self.light_bounces += other.light_bounces;
+        self.light_roulette_terminations += other.light_roulette_terminations;
+    }
+
+    pub fn mean_path_length(&self) -> f64 {
+        if self.paths == 0 {
+            return 0.0;
+        }
+        self.bounces as f64 / self.paths as f64
+    }
+
+    pub fn mean_light_path_length(&self) -> f64 {
+        if self.light_paths == 0 {
+            return 0.0;
+        }
+        self.light_bounces as f64 / self.light_paths as f64
+    }
+}
+
+/// Light that lands on arbitrary pixels rather than the one being sampled,
+/// such as light paths connected straight to the camera. Each thread keeps
+/// its own and they are summed at the end.
+pub struct SplatBuffer {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl SplatBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Vec3::zero(); width * height],
+        }
+    }
+
+    /// Adds `value` to the pixel whose camera rays `get_ray(s, t)` are, with
+    /// pixel `(i, j)` covering `[i, i + 1] / (width - 1)` and likewise for `t`.
+    pub fn add(&mut self, s: f64, t: f64, value: Vec3) {
+        let i = (s * (self.width - 1) as f64).floor();
+        let j = (t * (self.height - 1) as f64).floor();
+        if i < 0.0 || j < 0.0 || i >= self.width as f64 || j >= self.height as f64 {
+            return;
+        }
+        self.pixels[j as usize * self.width + i as usize] += value;
+    }
+
+    pub fn merge(&mut self, other: &SplatBuffer) {
+        for (pixel, value) in self.pixels.iter_mut().zip(other.pixels.iter()) {
+            *pixel += *value;
+        }
+    }
+
+    /// Sum of the splats at pixel `(i, j)`, with `j` counted from the bottom.
+    pub fn get(&self, i: usize, j: usize) -> Vec3 {
+        self.pixels[j * self.width + i]
+    }
+}
+
+/// Turns camera rays into radiance samples.
+pub trait Integrator: Send + Sync {
+    /// One estimate, as linear sRGB, of the radiance arriving along the camera
+    /// ray `r`. Light found for other pixels goes to `splats`.
+    fn radiance(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        splats: &mut SplatBuffer,
+        stats: &mut RenderStats,
+    ) -> Vec3;
+}
+
+/// Hands the per-path state of `r` on to the ray scattered at `hit_record`,
+/// entering or leaving the interior of transmissive surfaces.
+pub fn continue_path(r: &Ray, hit_record: &HitRecord, scattered: &mut Ray) {
+    scattered.wavelength = scattered.wavelength.or(r.wavelength);
+    scattered.media = r.media;
+    if let Some(absorption) = hit_record.mat_ptr.interior_absorption() {
+        if Vec3::dot(scattered.direction, hit_record.normal) < 0.0 {
+            if hit_record.front_face {
+                scattered.media.push(absorption);
+            } else {
+                scattered.media.pop();
+            }
+        }
+    }
+}
+
+/// Power heuristic weight of a sample taken with density `pdf` when `other`
+/// could also have produced it.
+pub fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    if other <= 0.0 {
+        return 1.0;
+    }
+    pdf * pdf / (pdf * pdf + other * other)
+}
+
+/// Emission seen at `hit_record` along `r`. When `r` came from a non-specular
+/// lobe, sampled with density `bsdf_pdf`, light sampling at its origin may
+/// have found the same light, so the two estimates are weighted against each
+/// other.
+fn emission(r: &Ray, hit_record: &HitRecord, scene: &Scene, bsdf_pdf: Option<f64>) -> Vec3 {
+    let emitted = hit_record.mat_ptr.emitted(hit_record);
+    match bsdf_pdf {
+        Some(pdf) if emitted != Vec3::zero() && hit_record.mat_ptr.is_emissive() => {
+            let light_pdf = if scene.lights.is_empty() {
+                0.0
+            } else {
+                scene.light_pdf(r.origin, r.direction, r.time)
+            };
+            emitted * power_heuristic(pdf, light_pdf)
+        }
+        _ => emitted,
+    }
+}
+
+/// Next event estimation: light reaching `hit_record` from a point sampled on
+/// the scene's lights, through the non-specular lobes of its material.
+pub fn direct_light(r: &Ray, hit_record: &HitRecord, scene: &Scene) -> Vec3 {
+    punctual_light(r, hit_record, scene)
+        + area_light(r, hit_record, scene)
+        + environment_light(r, hit_record, scene)
+}
+
+/// Light from a direction sampled on the background, if nothing is in the way.
+pub fn environment_light(r: &Ray, hit_record: &HitRecord, scene: &Scene) -> Vec3 {
+    if !scene.background.is_sampleable() {
+        return Vec3::zero();
+    }
+    let direction = scene.background.sample();
+    let f = hit_record.mat_ptr.eval(r, hit_record, direction);
+    let pdf = scene.background.pdf(direction);
+    if f == Vec3::zero() || pdf <= 0.0 {
+        return Vec3::zero();
+    }
+    let weight = power_heuristic(pdf, hit_record.mat_ptr.pdf(r, hit_record, direction));
+    let mut shadow_ray = Ray::new(hit_record.p, direction, r.time);
+    shadow_ray.wavelength = r.wavelength;
+    shadow_ray.media = r.media;
+    if scene.hit(&shadow_ray, 0.001, f64::INFINITY).is_some() {
+        return Vec3::zero();
+    }
+    let transmittance = shadow_ray.media.transmittance(f64::INFINITY);
+    f * scene.background.value(direction) * (transmittance * weight / pdf)
+}
+
+/// Radiance of the background behind `r`, weighted against light sampling
+/// like `emission`.
+pub fn background(r: &Ray, scene: &Scene, bsdf_pdf: Option<f64>) -> Vec3 {
+    let radiance = r.media.transmittance(f64::INFINITY) * scene.background.value(r.direction);
+    match bsdf_pdf {
+        Some(pdf) if scene.background.is_sampleable() => {
+            radiance * power_heuristic(pdf, scene.background.pdf(r.direction))
+        }
+        _ => radiance,
+    }
+}
+
+/// Light from every punctual light that is not blocked on the way.
+pub fn punctual_light(r: &Ray, hit_record: &HitRecord, scene: &Scene) -> Vec3 {
+    let mut sum = Vec3::zero();
+    for light in scene.punctual_lights.iter() {
+        let Some(sample) = light.sample(hit_record.p) else {
+            continue;
+        };
+        let f = hit_record.mat_ptr.eval(r, hit_record, sample.direction);
+        if f == Vec3::zero() {
+            continue;
+        }
+        let mut shadow_ray = Ray::new(hit_record.p, sample.direction, r.time);
+        shadow_ray.wavelength = r.wavelength;
+        shadow_ray.media = r.media;
+        if scene.hit(&shadow_ray, 0.001, sample.distance).is_none() {
+            let transmittance = shadow_ray.media.transmittance(sample.distance);
+            sum += f * sample.radiance * transmittance;
+        }
+    }
+    sum
+}
+
+/// Light from a point sampled on one of the emissive objects.
+fn area_light(r: &Ray, hit_record: &HitRecord, scene: &Scene) -> Vec3 {
+    let Some(direction) = scene.sample_light(hit_record.p, r.time) else {
+        return Vec3::zero();
+    };
+    let f = hit_record.mat_ptr.eval(r, hit_record, direction);
+    let pdf = scene.light_pdf(hit_record.p, direction, r.time);
+    if f == Vec3::zero() || pdf <= 0.0 {
+        return Vec3::zero();
+    }
+    let mut shadow_ray = Ray::new(hit_record.p, direction, r.time);
+    shadow_ray.wavelength = r.wavelength;
+    shadow_ray.media = r.media;
+    match scene.hit(&shadow_ray, 0.001, f64::INFINITY) {
+        Some(rec) if rec.mat_ptr.is_emissive() => {
+            let transmittance = shadow_ray.media.transmittance(rec.t * direction.length());
+            let weight = power_heuristic(pdf, hit_record.mat_ptr.pdf(r, hit_record, direction));
+            f * rec.mat_ptr.emitted(&rec) * (transmittance * weight / pdf)
+        }
+        _ => Vec3::zero(),
+    }
+}
+
+/// Light reaching `hit_record` through glass and mirrors, from the caustic
+/// photon map, or `None` where paths have to find it themselves: without a
+/// map and inside media.
+fn caustic_light(r: &Ray, hit_record: &HitRecord, scene: &Scene) -> Option<Vec3> {
+    let caustics = scene.caustics.as_ref()?;
+    if hit_record.mat_ptr.is_volumetric() {
+        return None;
+    }
+    Some(caustics.estimate(r, hit_record))
+}
+
+/// Density of `scatter` choosing the direction of `srec`, or `None` if it came
+/// from a specular lobe that light sampling cannot reach.
+pub fn scatter_pdf(r: &Ray, hit_record: &HitRecord, srec: &ScatterRecord) -> Option<f64> {
+    if srec.is_specular {
+        return None;
+    }
+    Some(
+        hit_record
+            .mat_ptr
+            .pdf(r, hit_record, srec.scattered.direction),
+    )
+}
+
+/// How a path goes on after a bounce, see `PathState::scatter`.
+pub enum Bounce {
+    /// The path carries on with this state, having survived Russian roulette
+    /// with the given probability.
+    Continue(PathState, f64),
+    /// The bounce let no light through, e.g. off a black surface.
+    Absorbed,
+    /// Russian roulette ended the path.
+    Terminated,
+}
+
+/// What a path carries from one bounce to the next.
+#[derive(Clone, Copy)]
+pub struct PathState {
+    /// Bounces left before the path is cut off.
+    pub depth: i32,
+    /// Bounces left before Russian roulette may end the path.
+    pub roulette_depth: i32,
+    /// Product of the weights so far, already divided by the survival
+    /// probabilities of earlier roulette rounds.
+    pub throughput: Vec3,
+    /// Density the ray was scattered with, see `emission`.
+    pub bsdf_pdf: Option<f64>,
+    /// The last non-specular bounce gathered light from `Scene::caustics`, so
+    /// lights reached from it through specular bounces alone may already be
+    /// counted.
+    pub caustics: bool,
+}
+
+impl PathState {
+    pub fn new(max_depth: i32, roulette_depth: i32) -> Self {
+        Self {
+            depth: max_depth,
+            roulette_depth,
+            throughput: Vec3::one(),
+            bsdf_pdf: None,
+            caustics: false,
+        }
+    }
+
+    /// Whether the emission at `hit_record`, reached along `r`, was already
+    /// gathered from the caustic photon map.
+    pub fn in_caustic_map(&self, r: &Ray, hit_record: &HitRecord, scene: &Scene) -> bool {
+        self.caustics
+            && self.bsdf_pdf.is_none()
+            && hit_record.mat_ptr.is_emissive()
+            && scene
+                .caustics
+                .as_ref()
+                .is_some_and(|caustics| caustics.covers(scene, r, hit_record))
+    }
+
+    /// State after a bounce with weight `attenuation`, and the probability
+    /// with which it survived Russian roulette. Paths survive in proportion
+    /// to their throughput, so dividing what they gather by that probability
+    /// keeps the estimate unbiased.
+    pub fn scatter(&self, attenuation: Vec3, bsdf_pdf: Option<f64>) -> Bounce {
+        let throughput = self.throughput * attenuation;
+        let survival = if self.roulette_depth > 0 {
+            1.0
+        } else {
+            throughput
+                .x()
+                .max(throughput.y())
+                .max(throughput.z())
+                .min(1.0)
+        };
+        if survival <= 0.0 {
+            return Bounce::Absorbed;
+        }
+        if rand::thread_rng().gen::<f64>() >= survival {
+            return Bounce::Terminated;
+        }
+        let next = Self {
+            depth: self.depth - 1,
+            roulette_depth: self.roulette_depth - 1,
+            throughput: throughput / survival,
+            bsdf_pdf,
+            caustics: self.caustics,
+        };
+        Bounce::Continue(next, survival)
+    }
+}
+
+/// Unidirectional path tracer with next event estimation, multiple importance
+/// sampling against the BSDF and Russian roulette.
+///
+/// In `spectral` mode each path carries a few wavelengths, and RGB weights and
+/// emission are upsampled to spectra at every vertex.
+#[derive(Clone, Copy)]
+pub struct PathTracer {
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+    pub spectral: bool,
+}
+
+impl PathTracer {
+    pub fn new(max_depth: i32) -> Self {
+        Self {
+            max_depth,
+            roulette_depth: 3,
+            spectral: false,
+        }
+    }
+
+    /// Number of bounces before Russian roulette may end a path.
+    pub fn with_roulette_depth(mut self, roulette_depth: i32) -> Self {
+        self.roulette_depth = roulette_depth;
+        self
+    }
+
+    pub fn with_spectral(mut self, spectral: bool) -> Self {
+        self.spectral = spectral;
+        self
+    }
+
+    fn trace(&self, camera_ray: &Ray, scene: &Scene, stats: &mut RenderStats) -> Vec3 {
+        let mut radiance = Vec3::zero();
+        let mut path = PathState::new(self.max_depth, self.roulette_depth);
+        let mut r = *camera_ray;
+        while path.depth > 0 {
+            let Some(hit_record) = scene.hit(&r, 0.001, f64::INFINITY) else {
+                radiance += path.throughput * background(&r, scene, path.bsdf_pdf);
+                break;
+            };
+            let emitted = if path.in_caustic_map(&r, &hit_record, scene) {
+                Vec3::zero()
+            } else {
+                emission(&r, &hit_record, scene, path.bsdf_pdf)
+            };
+            let transmittance = r.media.transmittance(hit_record.t * r.direction.length());
+            let Some(mut srec) = hit_record.mat_ptr.scatter(&r, &hit_record) else {
+                radiance += path.throughput * transmittance * emitted;
+                break;
+            };
+            stats.bounces += 1;
+            continue_path(&r, &hit_record, &mut srec.scattered);
+            let direct = direct_light(&r, &hit_record, scene);
+            let caustics = caustic_light(&r, &hit_record, scene);
+            let gathered = emitted + direct + caustics.unwrap_or(Vec3::zero());
+            radiance += path.throughput * transmittance * gathered;
+
+            let pdf = scatter_pdf(&r, &hit_record, &srec);
+            match path.scatter(transmittance * srec.attenuation, pdf) {
+                Bounce::Continue(mut next, _) => {
+                    if !srec.is_specular {
+                        next.caustics = caustics.is_some();
+                    }
+                    path = next;
+                }
+                Bounce::Absorbed => break,
+                Bounce::Terminated => {
+                    stats.roulette_terminations += 1;
+                    break;
+                }
+            }
+            r = srec.scattered;
+        }
+        radiance
+    }
+
+    /// `trace` with spectral weights. Roulette still looks at the RGB
+    /// throughput, which only affects the noise.
+    fn trace_spectral(
+        &self,
+        camera_ray: &Ray,
+        wavelengths: &mut SampledWavelengths,
+        scene: &Scene,
+        stats: &mut RenderStats,
+    ) -> SampledSpectrum {
+        let mut radiance = SampledSpectrum::zero();
+        let mut throughput = SampledSpectrum::from_rgb(Vec3::one(), wavelengths);
+        let mut path = PathState::new(self.max_depth, self.roulette_depth);
+        let mut r = *camera_ray;
+        while path.depth > 0 {
+            let Some(hit_record) = scene.hit(&r, 0.001, f64::INFINITY) else {
+                let background = background(&r, scene, path.bsdf_pdf);
+                radiance =
+                    radiance + throughput * SampledSpectrum::from_rgb(background, wavelengths);
+                break;
+            };
+            let emitted = if path.in_caustic_map(&r, &hit_record, scene) {
+                Vec3::zero()
+            } else {
+                emission(&r, &hit_record, scene, path.bsdf_pdf)
+            };
+            let emitted = SampledSpectrum::from_rgb(emitted, wavelengths);
+            let transmittance_rgb = r.media.transmittance(hit_record.t * r.direction.length());
+            let transmittance = SampledSpectrum::from_rgb(transmittance_rgb, wavelengths);
+            let Some(mut srec) = hit_record.mat_ptr.scatter(&r, &hit_record) else {
+                radiance = radiance + throughput * transmittance * emitted;
+                break;
+            };
+            stats.bounces += 1;
+            if hit_record.mat_ptr.is_dispersive() {
+                wavelengths.terminate_secondary();
+            }
+            continue_path(&r, &hit_record, &mut srec.scattered);
+            let caustics = caustic_light(&r, &hit_record, scene);
+            let gathered = direct_light(&r, &hit_record, scene) + caustics.unwrap_or(Vec3::zero());
+            let gathered = SampledSpectrum::from_rgb(gathered, wavelengths);
+            radiance = radiance + throughput * transmittance * (emitted + gathered);
+
+            let pdf = scatter_pdf(&r, &hit_record, &srec);
+            match path.scatter(transmittance_rgb * srec.attenuation, pdf) {
+                Bounce::Continue(mut next, survival) => {
+                    let attenuation = SampledSpectrum::from_rgb(srec.attenuation, wavelengths);
+                    throughput = throughput * transmittance * attenuation / survival;
+                    if !srec.is_specular {
+                        next.caustics = caustics.is_some();
+                    }
+                    path = next;
+                }
+                Bounce::Absorbed => break,
+                Bounce::Terminated => {
+                    stats.roulette_terminations += 1;
+                    break;
+                }
+            }
+            r = srec.scattered;
+        }
+        radiance
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        _splats: &mut SplatBuffer,
+        stats: &mut RenderStats,
+    ) -> Vec3 {
+        stats.paths += 1;
+        if !self.spectral {
+            return self.trace(r, scene, stats);
+        }
+        let mut wavelengths = SampledWavelengths::sample_uniform(rand::thread_rng().gen());
+        let mut r = *r;
+        r.wavelength = Some(wavelengths.hero());
+        self.trace_spectral(&r, &mut wavelengths, scene, stats)
+            .to_rgb(&wavelengths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+
+    #[test]
+    fn test_roulette_keeps_expected_throughput() {
+        let attenuation = Vec3::new(0.3, 0.6, 0.1);
+        let path = PathState::new(10, 0);
+        let n = 100000;
+        let mut mean = Vec3::zero();
+        let mut terminated = 0;
+        for _ in 0..n {
+            match path.scatter(attenuation, None) {
+                Bounce::Continue(next, survival) => {
+                    assert_eq!(survival, 0.6);
+                    mean += next.throughput / n as f64;
+                }
+                Bounce::Absorbed => panic!("absorbed with throughput left"),
+                Bounce::Terminated => terminated += 1,
+            }
+        }
+        assert!((mean - attenuation).length() < 0.01);
+        assert!((terminated as f64 / n as f64 - 0.4).abs() < 0.01);
+
+        // Black surfaces absorb rather than lose at roulette, and paths
+        // before the roulette depth always survive.
+        assert!(matches!(path.scatter(Vec3::zero(), None), Bounce::Absorbed));
+        let early = PathState::new(10, 3);
+        assert!((0..100).all(|_| matches!(
+            early.scatter(attenuation, None),
+            Bounce::Continue(_, survival) if survival == 1.0
+        )));
+    }
+
+    #[test]
+    fn test_furnace() {
+        // A convex diffuse ball under a uniform white sky reflects exactly
+        // its albedo, whichever way it is seen.
+        let albedo = Box::new(SolidColor::new(Vec3::one() * 0.5));
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Vec3::zero(),
+            1.0,
+            Lambertian::new(albedo),
+        )));
+        let scene = Scene::new(world, Vec3::one());
+        let mut splats = SplatBuffer::new(1, 1);
+        let mut stats = RenderStats::default();
+        for spectral in [false, true] {
+            let tracer = PathTracer::new(10)
+                .with_roulette_depth(0)
+                .with_spectral(spectral);
+            let n = 20000;
+            let mut mean = Vec3::zero();
+            for _ in 0..n {
+                let target = Vec3::random_in_unit_sphere() * 0.9;
+                let r = Ray::new(
+                    Vec3::new(0.0, 0.0, 5.0),
+                    target - Vec3::new(0.0, 0.0, 5.0),
+                    0.0,
+                );
+                mean += tracer.radiance(&r, &scene, &mut splats, &mut stats) / n as f64;
+            }
+            assert!((mean - Vec3::one() * 0.5).length() < 0.03, "{:?}", mean);
+        }
+        assert_eq!(stats.paths, 40000);
+        assert_eq!(stats.bounces, 40000);
+    }
+}
@@ -0,0 +1,184 @@
+//! Lights without geometry. Rays never hit them, so they only contribute
+//! through explicit light sampling at each shading point.
+use crate::onb::Onb;
+use crate::spectrum::smoothstep;
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Light arriving at a shading point from one sampled direction.
+pub struct LightSample {
+    /// Unit vector from the shading point towards the light.
+    pub direction: Vec3,
+    /// Distance to the light, infinite for directional lights.
+    pub distance: f64,
+    /// Incident radiance divided by the sampling density, i.e. the irradiance
+    /// on a surface facing the light.
+    pub radiance: Vec3,
+}
+
+pub trait Light: Send + Sync {
+    fn sample(&self, p: Vec3) -> Option<LightSample>;
+}
+
+/// Smooth window taking the inverse square falloff to zero at `range`.
+fn range_attenuation(distance: f64, range: Option<f64>) -> f64 {
+    match range {
+        Some(range) => {
+            let ratio = distance / range;
+            (1.0 - ratio.powi(4)).clamp(0.0, 1.0).powi(2)
+        }
+        None => 1.0,
+    }
+}
+
+/// Emits `intensity` (radiant intensity per steradian) equally in all
+/// directions from `position`.
+pub struct PointLight {
+    pub position: Vec3,
+    pub intensity: Vec3,
+    pub range: Option<f64>,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+            range: None,
+        }
+    }
+
+    /// Fades the light out smoothly so it has no effect beyond `range`.
+    pub fn with_range(mut self, range: f64) -> Self {
+        self.range = Some(range);
+        self
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let falloff = range_attenuation(distance, self.range) / (distance * distance);
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.intensity * falloff,
+        })
+    }
+}
+
+/// Point light restricted to a cone around `direction`: full `intensity`
+/// within `inner_angle`, fading smoothly to nothing at `outer_angle`.
+pub struct SpotLight {
+    pub light: PointLight,
+    pub direction: Vec3,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    /// Cone angles are half angles in degrees.
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        inner_angle: f64,
+        outer_angle: f64,
+    ) -> Self {
+        let outer_angle = outer_angle.max(inner_angle);
+        Self {
+            light: PointLight::new(position, intensity),
+            direction: Vec3::unit_vector(direction),
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        }
+    }
+
+    pub fn with_range(mut self, range: f64) -> Self {
+        self.light.range = Some(range);
+        self
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let mut sample = self.light.sample(p)?;
+        let cos_theta = Vec3::dot(-sample.direction, self.direction);
+        let cone = if self.cos_inner > self.cos_outer {
+            smoothstep(self.cos_outer, self.cos_inner, cos_theta)
+        } else if cos_theta >= self.cos_inner {
+            1.0
+        } else {
+            0.0
+        };
+        if cone <= 0.0 {
+            return None;
+        }
+        sample.radiance *= cone;
+        Some(sample)
+    }
+}
+
+/// Distant light such as the sun, shining from `direction` with `irradiance`
+/// on a surface facing it. A non-zero angular diameter (degrees) spreads it
+/// over a small disk of directions, which gives soft shadows.
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub irradiance: Vec3,
+    cos_half_angle: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3, angular_diameter: f64) -> Self {
+        Self {
+            direction: Vec3::unit_vector(direction),
+            irradiance,
+            cos_half_angle: (0.5 * angular_diameter).to_radians().cos(),
+        }
+    }
+
+    /// The sun as seen from the ground, 0.53 degrees across.
+    pub fn sun(direction: Vec3, irradiance: Vec3) -> Self {
+        Self::new(direction, irradiance, 0.53)
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        // Uniform over the cone of the disk, whose radiance is the irradiance
+        // divided by its solid angle, so the two cancel.
+        let mut rng = rand::thread_rng();
+        let z = 1.0 + rng.gen::<f64>() * (self.cos_half_angle - 1.0);
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let sin_theta = (1.0 - z * z).sqrt();
+        let uvw = Onb::build_from_w(self.direction);
+        let direction = uvw.local(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z));
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spot_cone() {
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let spot = SpotLight::new(Vec3::new(0.0, 2.0, 0.0), down, Vec3::one(), 20.0, 40.0);
+        let at = |x: f64| spot.sample(Vec3::new(x, 0.0, 0.0)).map(|s| s.radiance.x());
+        assert_eq!(at(0.0), Some(0.25));
+        // 30 degrees off axis is halfway through the soft edge.
+        let edge = at(2.0 * 30f64.to_radians().tan()).unwrap();
+        assert!(edge > 0.0 && edge < 0.25 * 0.75);
+        assert!(at(2.0).is_none());
+    }
+}
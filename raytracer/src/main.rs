@@ -1,35 +1,55 @@
 mod aabb;
 mod aarect;
+mod atmosphere;
+mod background;
+mod bdpt;
 mod box_;
 mod camera;
 mod color;
 mod constant_medium;
+mod heterogeneous_medium;
 mod hittable;
 mod hittable_list;
+mod integrator;
+mod light;
 mod material;
+mod microfacet;
 mod moving_sphere;
+mod onb;
 mod perlin;
+mod phase;
+mod photon_map;
+mod principled;
 mod ray;
+mod scene;
+mod sky;
+mod spectrum;
 mod sphere;
+mod subsurface;
 mod texture;
 mod vec3;
+mod volume_grid;
 
 use aarect::{XyRect, XzRect, YzRect};
+use atmosphere::Atmosphere;
+use bdpt::BidirectionalPathTracer;
 use box_::Box_;
 use camera::Camera;
 use color::write_color;
 use constant_medium::ConstantMedium;
-use hittable::{Hittable, RotateY, Translate};
+use hittable::{RotateY, Translate};
 use hittable_list::HittableList;
 use image::{ImageBuffer, RgbImage};
 use indicatif::ProgressBar;
+use integrator::{Integrator, PathTracer, RenderStats, SplatBuffer};
 use material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Metal};
 use moving_sphere::MovingSphere;
+use phase::IsotropicPhase;
+use photon_map::PhotonMap;
 use rand::Rng;
-use ray::Ray;
+use scene::Scene;
 use sphere::Sphere;
 use std::fs::File;
-use std::ops::Deref;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -45,25 +65,6 @@ fn is_ci() -> bool {
     option_env!("CI").unwrap_or_default() == "true"
 }
 
-fn ray_color(r: &Ray, background: &Vec3, world: &dyn Hittable, depth: i32) -> Vec3 {
-    if depth <= 0 {
-        return Vec3::zero();
-    }
-
-    if let Some(hit_record) = world.hit(*r, 0.001, f64::INFINITY) {
-        let emitted = hit_record
-            .mat_ptr
-            .emitted(hit_record.u, hit_record.v, &hit_record.p);
-        if let Some((scattered, attenuation)) = hit_record.mat_ptr.scatter(r, &hit_record) {
-            emitted + attenuation * ray_color(&scattered, background, world, depth - 1)
-        } else {
-            return emitted;
-        }
-    } else {
-        return *background;
-    }
-}
-
 fn clamp(x: f64) -> f64 {
     if x < 0.0 {
         0.0
@@ -144,15 +145,6 @@ fn new_world() -> HittableList {
         Box::new(m),
     )));
 
-    let boundary = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 5000.0, Dielectric::new(1.5));
-    let t = SolidColor::new(Vec3::new(1.0, 1.0, 1.0));
-    let m = Isotropic::new(Box::new(t));
-    world.add(Box::new(ConstantMedium::new(
-        0.00001,
-        Box::new(boundary),
-        Box::new(m),
-    )));
-
     let earth_texture = ImageTexture::new(&Path::new("earth.jpg"));
     world.add(Box::new(Sphere::new(
         Vec3::new(400.0, 200.0, 400.0),
@@ -190,6 +182,18 @@ fn main() {
     let path = "output/test_.jpg";
     let quality = 250; // From 0 to 100, suggested value: 60
     let max_depth = 50;
+    // Bounces before Russian roulette may end a path
+    let roulette_depth = 3;
+    // Trace a few wavelengths per path instead of RGB triples
+    let spectral = false;
+    // Also trace paths from the lights, for light reaching the camera through
+    // small gaps or via glass
+    let bidirectional = false;
+    // Photons traced through glass and mirrors for the path tracer to gather
+    // caustics from, and the radius it gathers them over, suggested value:
+    // 20000 photons. With none, paths find caustics by themselves
+    let caustic_photons = 0;
+    let caustic_radius = 5.0;
     let aspect_ratio = 1.0;
 
     // Create image data
@@ -223,47 +227,99 @@ fn main() {
         1.0,
     );
 
-    let counter = Arc::new(Mutex::new(img));
+    let integrator: Arc<dyn Integrator> = if bidirectional {
+        Arc::new(BidirectionalPathTracer::new(cam, max_depth).with_roulette_depth(roulette_depth))
+    } else {
+        Arc::new(
+            PathTracer::new(max_depth)
+                .with_roulette_depth(roulette_depth)
+                .with_spectral(spectral),
+        )
+    };
+
+    // Linear pixel colours, bottom row first
+    let film = Arc::new(Mutex::new(vec![Vec3::zero(); width * height]));
     let mut handles = vec![];
 
+    let haze = Atmosphere::new(
+        0.00001,
+        Box::new(SolidColor::new(Vec3::one())),
+        Box::new(IsotropicPhase),
+    )
+    .with_extent(Vec3::zero(), 5000.0);
+    let mut scene = Scene::new(new_world(), background).with_atmosphere(haze);
+    if !bidirectional && caustic_photons > 0 {
+        let caustics = PhotonMap::caustics(&scene, caustic_photons, caustic_radius);
+        scene = scene.with_caustics(caustics);
+    }
+    let scene = Arc::new(scene);
+
     for k in 0..15 {
-        let counter = Arc::clone(&counter);
+        let film = Arc::clone(&film);
+        let integrator = Arc::clone(&integrator);
+        let scene = Arc::clone(&scene);
         let handle = thread::spawn(move || {
-            let mut world = new_world();
+            let mut stats = RenderStats::default();
+            let mut splats = SplatBuffer::new(width, height);
             for j in (height - height / 15 * (k + 1)..=height - height / 15 * k - 1).rev() {
                 for i in 0..width {
                     let mut rng = rand::thread_rng();
-                    let mut pixel_color: [u8; 3] = [0, 0, 0];
                     let mut pixel_color_ = Vec3::zero();
                     for _s in 0..quality {
                         let u = (i as f64 + rng.gen::<f64>()) / (width - 1) as f64;
                         let v = (j as f64 + rng.gen::<f64>()) / (height - 1) as f64;
                         let r = cam.get_ray(u, v);
-                        pixel_color_ += ray_color(&r, &background, &world, max_depth);
+                        pixel_color_ += integrator.radiance(&r, &scene, &mut splats, &mut stats);
                     }
-                    pixel_color[0] +=
-                        (clamp((pixel_color_.x() / quality as f64).sqrt()) * 255.999) as u8;
-                    pixel_color[1] +=
-                        (clamp((pixel_color_.y() / quality as f64).sqrt()) * 255.999) as u8;
-                    pixel_color[2] +=
-                        (clamp((pixel_color_.z() / quality as f64).sqrt()) * 255.999) as u8;
-                    let mut img = counter.lock().unwrap();
-                    write_color(pixel_color, &mut img, i, height - j - 1);
+                    let mut film = film.lock().unwrap();
+                    film[j * width + i] = pixel_color_ / quality as f64;
                     //bar.inc(1);
                 }
             }
-            world.clear();
+            (stats, splats)
         });
         handles.push(handle);
     }
 
+    let mut stats = RenderStats::default();
+    let mut splats = SplatBuffer::new(width, height);
     for handle in handles {
-        handle.join().unwrap();
+        let (thread_stats, thread_splats) = handle.join().unwrap();
+        stats.merge(&thread_stats);
+        splats.merge(&thread_splats);
+    }
+    println!(
+        "{} paths, {:.2} bounces per path, {} ended by Russian roulette",
+        stats.paths,
+        stats.mean_path_length(),
+        stats.roulette_terminations
+    );
+    if stats.light_paths > 0 {
+        println!(
+            "{} light subpaths, {:.2} bounces per subpath, {} ended by Russian roulette",
+            stats.light_paths,
+            stats.mean_light_path_length(),
+            stats.light_roulette_terminations
+        );
     }
 
-    let img = counter.lock().unwrap();
+    // Every path splats over the whole film, of which one pixel covers
+    // 1 / ((width - 1) * (height - 1)) in camera coordinates
+    let splat_scale = ((width - 1) * (height - 1)) as f64 / stats.paths.max(1) as f64;
+    let film = film.lock().unwrap();
+    for j in 0..height {
+        for i in 0..width {
+            let color = film[j * width + i] + splats.get(i, j) * splat_scale;
+            let pixel_color = [
+                (clamp(color.x().sqrt()) * 255.999) as u8,
+                (clamp(color.y().sqrt()) * 255.999) as u8,
+                (clamp(color.z().sqrt()) * 255.999) as u8,
+            ];
+            write_color(pixel_color, &mut img, i, height - j - 1);
+        }
+    }
 
-    let Img: RgbImage = img.deref().clone();
+    let Img: RgbImage = img;
 
     // Finish progress bar
     //bar.finish();
@@ -1,15 +1,81 @@
 use crate::hittable::HitRecord;
+use crate::microfacet;
+use crate::onb::Onb;
+use crate::phase::{IsotropicPhase, PhaseFunction};
 use crate::ray::Ray;
-use crate::texture::Texture;
+use crate::spectrum::{self, RefractiveIndex};
+use crate::texture::{BlackbodyTexture, SolidColor, Texture};
 use crate::vec3::Vec3;
 use rand::Rng;
+use std::f64::consts::PI;
+use std::sync::OnceLock;
 
-pub trait Material {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)>;
+/// Outcome of sampling a material: the continued ray and its throughput weight.
+pub struct ScatterRecord {
+    pub scattered: Ray,
+    pub attenuation: Vec3,
+    /// Sampled from a lobe that `Material::eval` leaves out, such as a mirror
+    /// reflection, so lights are only found by following `scattered`.
+    pub is_specular: bool,
+}
+
+impl ScatterRecord {
+    pub fn new(scattered: Ray, attenuation: Vec3, is_specular: bool) -> Self {
+        Self {
+            scattered,
+            attenuation,
+            is_specular,
+        }
+    }
+}
+
+pub trait Material: Send + Sync {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord>;
+
+    /// Scattering function times the cosine at the surface, or the phase
+    /// function in media, towards `direction`. Covers the lobes that `scatter`
+    /// does not flag as specular, which are lit by sampling lights directly.
+    fn eval(&self, _r_in: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Solid angle density with which `scatter` picks `direction` from the
+    /// lobes covered by `eval`, for weighting it against light sampling.
+    fn pdf(&self, _r_in: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> f64 {
+        0.0
+    }
 
-    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+    fn emitted(&self, hit_record: &HitRecord) -> Vec3 {
         Vec3::zero()
     }
+
+    /// Whether surfaces with this material are light sources that can be
+    /// sampled directly.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// Tells a light given in total power the area of the surface it lies
+    /// on, which `Scene::new` looks up from the geometry.
+    fn set_emitting_area(&self, _area: f64) {}
+
+    /// Whether the scattered direction depends on `Ray::wavelength`.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    /// Whether this scatters inside participating media rather than at a
+    /// surface.
+    fn is_volumetric(&self) -> bool {
+        false
+    }
+
+    /// Absorption coefficient inside surfaces that let light through. Rays
+    /// transmitted through a front face enter this medium, rays transmitted
+    /// through a back face leave it.
+    fn interior_absorption(&self) -> Option<Vec3> {
+        None
+    }
 }
 
 pub struct Lambertian {
@@ -23,7 +89,7 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
         let mut scatter_direction = hit_record.normal + Vec3::random_unit_vector();
         if scatter_direction.near_zero() {
             scatter_direction = hit_record.normal;
@@ -31,7 +97,20 @@ impl Material for Lambertian {
 
         let scattered: Ray = Ray::new(hit_record.p, scatter_direction, r_in.time);
         let attenuation = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
-        Some((scattered, attenuation))
+        Some(ScatterRecord::new(scattered, attenuation, false))
+    }
+
+    fn eval(&self, _r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        let cosine = Vec3::dot(Vec3::unit_vector(direction), hit_record.normal);
+        if cosine <= 0.0 {
+            return Vec3::zero();
+        }
+        self.albedo.value(hit_record.u, hit_record.v, &hit_record.p) * (cosine / PI)
+    }
+
+    fn pdf(&self, _r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let cosine = Vec3::dot(Vec3::unit_vector(direction), hit_record.normal);
+        cosine.max(0.0) / PI
     }
 }
 
@@ -47,10 +126,28 @@ impl Metal {
             fuzz: if f < 1.0 { f } else { 1.0 },
         }
     }
+
+    /// Density of the direction `direction` through a point picked uniformly
+    /// in the fuzz ball around the mirror direction `reflected`, i.e. the
+    /// integral of `t^2` along the chord through the ball over its volume.
+    fn fuzz_pdf(&self, reflected: Vec3, direction: Vec3) -> f64 {
+        let d = Vec3::unit_vector(direction);
+        let b = Vec3::dot(d, reflected);
+        let discriminant = b * b - (reflected.squared_length() - self.fuzz * self.fuzz);
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let t_far = b + discriminant.sqrt();
+        if t_far <= 0.0 {
+            return 0.0;
+        }
+        let t_near = (b - discriminant.sqrt()).max(0.0);
+        (t_far.powi(3) - t_near.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
         let unit_ray_direction = Vec3::unit_vector(r_in.direction);
         let reflected = Vec3::reflect(unit_ray_direction, hit_record.normal);
         let scattered = Ray::new(
@@ -60,73 +157,547 @@ impl Material for Metal {
         );
         let attenuation = self.albedo;
         if Vec3::dot(scattered.direction, hit_record.normal) > 0.0 {
-            Some((scattered, attenuation))
+            Some(ScatterRecord::new(scattered, attenuation, self.fuzz <= 0.0))
         } else {
             None
         }
     }
+
+    fn eval(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        // Every sample is weighted by the albedo alone, so the lobe is the
+        // sampling density scaled by it.
+        self.albedo * self.pdf(r_in, hit_record, direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        if self.fuzz <= 0.0 || Vec3::dot(direction, hit_record.normal) <= 0.0 {
+            return 0.0;
+        }
+        let reflected = Vec3::reflect(Vec3::unit_vector(r_in.direction), hit_record.normal);
+        self.fuzz_pdf(reflected, direction)
+    }
+}
+
+/// Rough conductor using the GGX distribution with Smith masking-shadowing and
+/// visible normal sampling. The Fresnel term uses the complex IOR `eta + ik`.
+pub struct Conductor {
+    pub eta: Vec3,
+    pub k: Vec3,
+    pub roughness: Box<dyn Texture>,
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: Box<dyn Texture>) -> Self {
+        Self { eta, k, roughness }
+    }
+
+    pub fn gold(roughness: Box<dyn Texture>) -> Self {
+        Self::new(
+            Vec3::new(0.143, 0.374, 1.442),
+            Vec3::new(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: Box<dyn Texture>) -> Self {
+        Self::new(
+            Vec3::new(0.200, 0.924, 1.102),
+            Vec3::new(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: Box<dyn Texture>) -> Self {
+        Self::new(
+            Vec3::new(1.657, 0.880, 0.521),
+            Vec3::new(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: Box<dyn Texture>) -> Self {
+        Self::new(
+            Vec3::new(0.155, 0.117, 0.138),
+            Vec3::new(4.828, 3.122, 2.147),
+            roughness,
+        )
+    }
+
+    /// Directions towards the viewer and towards `direction` in the shading
+    /// frame with the GGX alpha at the hit, unless the surface is a mirror
+    /// there or either direction is below it.
+    fn local_frame(
+        &self,
+        r_in: &Ray,
+        hit_record: &HitRecord,
+        direction: Vec3,
+    ) -> Option<(Vec3, Vec3, f64)> {
+        let roughness = self
+            .roughness
+            .value(hit_record.u, hit_record.v, &hit_record.p)
+            .x();
+        let alpha = microfacet::roughness_to_alpha(roughness);
+        if alpha <= microfacet::MIN_ALPHA {
+            return None;
+        }
+        let uvw = Onb::build_from_w(hit_record.normal);
+        let wo = uvw.to_local(-Vec3::unit_vector(r_in.direction));
+        let wi = uvw.to_local(Vec3::unit_vector(direction));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return None;
+        }
+        Some((wo, wi, alpha))
+    }
 }
 
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let roughness = self
+            .roughness
+            .value(hit_record.u, hit_record.v, &hit_record.p)
+            .x();
+        let alpha = microfacet::roughness_to_alpha(roughness);
+        let uvw = Onb::build_from_w(hit_record.normal);
+        let wo = uvw.to_local(-Vec3::unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let m = microfacet::sample_ggx_vndf(wo, alpha, rng.gen(), rng.gen());
+        let wi = Vec3::reflect(-wo, m);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        // With visible normal sampling the D and pdf terms cancel, leaving F * G2 / G1.
+        let fresnel = microfacet::fresnel_conductor(Vec3::dot(wo, m), self.eta, self.k);
+        let attenuation =
+            fresnel * (microfacet::smith_g2(wo, wi, alpha) / microfacet::smith_g1(wo, alpha));
+        let scattered = Ray::new(hit_record.p, uvw.local(wi), r_in.time);
+        Some(ScatterRecord::new(
+            scattered,
+            attenuation,
+            alpha <= microfacet::MIN_ALPHA,
+        ))
+    }
+
+    fn eval(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        let Some((wo, wi, alpha)) = self.local_frame(r_in, hit_record, direction) else {
+            return Vec3::zero();
+        };
+        let m = Vec3::unit_vector(wo + wi);
+        let fresnel = microfacet::fresnel_conductor(Vec3::dot(wo, m), self.eta, self.k);
+        fresnel * microfacet::reflection(wo, wi, alpha)
+    }
+
+    fn pdf(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        match self.local_frame(r_in, hit_record, direction) {
+            Some((wo, wi, alpha)) => microfacet::reflection_pdf(wo, wi, alpha),
+            None => 0.0,
+        }
+    }
+}
+
+/// Glass-like material. A non-zero `roughness` gives frosted glass through a
+/// GGX microfacet interface, and a dispersive `ior` splits light by wavelength.
+/// Light travelling inside is attenuated by `absorption` per unit distance.
 pub struct Dielectric {
-    pub ref_idx: f64,
+    pub ior: RefractiveIndex,
+    pub roughness: f64,
+    pub absorption: Vec3,
 }
 
 impl Dielectric {
     pub fn new(ri: f64) -> Self {
-        Self { ref_idx: ri }
+        Self::rough(ri, 0.0)
+    }
+
+    pub fn rough(ri: f64, roughness: f64) -> Self {
+        Self::dispersive(RefractiveIndex::Constant(ri), roughness)
+    }
+
+    pub fn dispersive(ior: RefractiveIndex, roughness: f64) -> Self {
+        Self {
+            ior,
+            roughness,
+            absorption: Vec3::zero(),
+        }
+    }
+
+    /// Tints the interior so that light keeps `color` of its energy after
+    /// travelling one unit at `density` one, e.g. wine or deep water.
+    pub fn with_absorption(mut self, color: Vec3, density: f64) -> Self {
+        let sigma = |c: f64| -f64::ln(c.clamp(1e-6, 1.0)) * density;
+        self.absorption = Vec3::new(sigma(color.x()), sigma(color.y()), sigma(color.z()));
+        self
+    }
+
+    fn scatter_rough(
+        &self,
+        unit_direction: Vec3,
+        hit_record: &HitRecord,
+        etai_over_etat: f64,
+    ) -> Option<(Vec3, f64)> {
+        let alpha = microfacet::roughness_to_alpha(self.roughness);
+        let uvw = Onb::build_from_w(hit_record.normal);
+        let wo = uvw.to_local(-unit_direction);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let m = microfacet::sample_ggx_vndf(wo, alpha, rng.gen(), rng.gen());
+        let cos_theta = f64::min(Vec3::dot(wo, m), 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let reflect_prob = if etai_over_etat * sin_theta > 1.0 {
+            1.0
+        } else {
+            Vec3::schlick(cos_theta, etai_over_etat)
+        };
+        let wi = if rng.gen_range(0.0..1.0) < reflect_prob {
+            let wi = Vec3::reflect(-wo, m);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = Vec3::refract(-wo, m, etai_over_etat);
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            wi
+        };
+        // Fresnel is accounted for by the lobe choice, leaving the shadowing ratio.
+        let g = microfacet::smith_g2(wo, wi, alpha) / microfacet::smith_g1(wo, alpha);
+        Some((uvw.local(wi), g))
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
-        let attenuation = Vec3::new(1.0, 1.0, 1.0);
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let mut attenuation = Vec3::new(1.0, 1.0, 1.0);
+        let mut rng = rand::thread_rng();
+        let wavelength = match r_in.wavelength {
+            None if self.ior.is_dispersive() => {
+                let lambda = spectrum::sample_wavelength(rng.gen());
+                attenuation = spectrum::wavelength_to_rgb_weight(lambda);
+                Some(lambda)
+            }
+            w => w,
+        };
+        let ref_idx = self.ior.at(wavelength.unwrap_or(spectrum::LAMBDA_D));
         let etai_over_etat = if hit_record.front_face {
-            1.0 / self.ref_idx
+            1.0 / ref_idx
         } else {
-            self.ref_idx
+            ref_idx
         };
         let unit_direction = Vec3::unit_vector(r_in.direction);
-        let cos_theta = if Vec3::dot(-unit_direction, hit_record.normal) < 1.0 {
-            Vec3::dot(-unit_direction, hit_record.normal)
+
+        let direction = if self.roughness > 0.0 {
+            let (direction, g) = self.scatter_rough(unit_direction, hit_record, etai_over_etat)?;
+            attenuation *= g;
+            direction
         } else {
-            1.0
+            let cos_theta = if Vec3::dot(-unit_direction, hit_record.normal) < 1.0 {
+                Vec3::dot(-unit_direction, hit_record.normal)
+            } else {
+                1.0
+            };
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let reflect_prob = Vec3::schlick(cos_theta, etai_over_etat);
+            if etai_over_etat * sin_theta > 1.0 || rng.gen_range(0.0..1.0) < reflect_prob {
+                Vec3::reflect(unit_direction, hit_record.normal)
+            } else {
+                Vec3::refract(unit_direction, hit_record.normal, etai_over_etat)
+            }
         };
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-        if etai_over_etat * sin_theta > 1.0 {
-            let reflected = Vec3::reflect(unit_direction, hit_record.normal);
-            let scattered = Ray::new(hit_record.p, reflected, r_in.time);
-            return Some((scattered, attenuation));
+        let mut scattered = Ray::new(hit_record.p, direction, r_in.time);
+        scattered.wavelength = wavelength;
+        Some(ScatterRecord::new(scattered, attenuation, true))
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.ior.is_dispersive()
+    }
+
+    fn interior_absorption(&self) -> Option<Vec3> {
+        Some(self.absorption)
+    }
+}
+
+/// A smooth dielectric coat over any base material, e.g. car paint or varnish.
+/// Rays either reflect off the coat or pass through it to the base, losing
+/// energy to the `tint` of the coat on the way in and out.
+pub struct Coated<Base: Material> {
+    pub base: Base,
+    pub ior: f64,
+    /// Colour of light crossing `thickness == 1` of coat at normal incidence.
+    pub tint: Vec3,
+    pub thickness: f64,
+}
+
+impl<Base: Material> Coated<Base> {
+    pub fn new(base: Base, ior: f64) -> Self {
+        Self::tinted(base, ior, Vec3::one(), 0.0)
+    }
+
+    pub fn tinted(base: Base, ior: f64, tint: Vec3, thickness: f64) -> Self {
+        Self {
+            base,
+            ior,
+            tint,
+            thickness,
+        }
+    }
+
+    /// Beer-Lambert transmittance of the coat along a path entering with
+    /// `cos_in` and leaving with `cos_out`.
+    fn transmittance(&self, cos_in: f64, cos_out: f64) -> Vec3 {
+        if self.thickness <= 0.0 {
+            return Vec3::one();
+        }
+        let refracted_cos = |cos: f64| {
+            let sin2 = (1.0 - cos * cos) / (self.ior * self.ior);
+            f64::max((1.0 - sin2).sqrt(), 1e-4)
+        };
+        let length = self.thickness * (1.0 / refracted_cos(cos_in) + 1.0 / refracted_cos(cos_out));
+        Vec3::new(
+            self.tint.x().powf(length),
+            self.tint.y().powf(length),
+            self.tint.z().powf(length),
+        )
+    }
+}
+
+impl<Base: Material> Material for Coated<Base> {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        if !hit_record.front_face {
+            return self.base.scatter(r_in, hit_record);
         }
-        let reflect_prob = Vec3::schlick(cos_theta, etai_over_etat);
+        let unit_direction = Vec3::unit_vector(r_in.direction);
+        let cos_in = f64::min(Vec3::dot(-unit_direction, hit_record.normal), 1.0);
         let mut rng = rand::thread_rng();
-        if rng.gen_range(0.0..1.0) < reflect_prob {
+        if rng.gen_range(0.0..1.0) < Vec3::schlick(cos_in, self.ior) {
             let reflected = Vec3::reflect(unit_direction, hit_record.normal);
             let scattered = Ray::new(hit_record.p, reflected, r_in.time);
-            return Some((scattered, attenuation));
+            return Some(ScatterRecord::new(scattered, Vec3::one(), true));
+        }
+
+        let mut rec = self.base.scatter(r_in, hit_record)?;
+        let cos_out = Vec3::dot(
+            Vec3::unit_vector(rec.scattered.direction),
+            hit_record.normal,
+        )
+        .abs();
+        let leaving = 1.0 - Vec3::schlick(cos_out, self.ior);
+        rec.attenuation = rec.attenuation * self.transmittance(cos_in, cos_out) * leaving;
+        Some(rec)
+    }
+
+    fn eval(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        let base = self.base.eval(r_in, hit_record, direction);
+        if !hit_record.front_face {
+            return base;
         }
-        let refracted = Vec3::refract(unit_direction, hit_record.normal, etai_over_etat);
-        let scattered = Ray::new(hit_record.p, refracted, r_in.time);
-        Some((scattered, attenuation))
+        let cos_in = f64::min(
+            Vec3::dot(-Vec3::unit_vector(r_in.direction), hit_record.normal),
+            1.0,
+        );
+        let cos_out = Vec3::dot(Vec3::unit_vector(direction), hit_record.normal).abs();
+        let entering = 1.0 - Vec3::schlick(cos_in, self.ior);
+        let leaving = 1.0 - Vec3::schlick(cos_out, self.ior);
+        base * self.transmittance(cos_in, cos_out) * (entering * leaving)
+    }
+
+    fn pdf(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let base = self.base.pdf(r_in, hit_record, direction);
+        if !hit_record.front_face {
+            return base;
+        }
+        let cos_in = f64::min(
+            Vec3::dot(-Vec3::unit_vector(r_in.direction), hit_record.normal),
+            1.0,
+        );
+        base * (1.0 - Vec3::schlick(cos_in, self.ior))
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Vec3 {
+        self.base.emitted(hit_record)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn set_emitting_area(&self, area: f64) {
+        self.base.set_emitting_area(area);
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+
+    fn interior_absorption(&self) -> Option<Vec3> {
+        self.base.interior_absorption()
+    }
+}
+
+/// Blends two materials across a surface, e.g. rust patches on metal. Each hit
+/// scatters off `b` with the probability given by the first channel of
+/// `weight` at the hit point, and off `a` otherwise.
+pub struct MixMaterial<A: Material, B: Material> {
+    pub a: A,
+    pub b: B,
+    pub weight: Box<dyn Texture>,
+}
+
+impl<A: Material, B: Material> MixMaterial<A, B> {
+    pub fn new(a: A, b: B, weight: Box<dyn Texture>) -> Self {
+        Self { a, b, weight }
+    }
+
+    pub fn constant(a: A, b: B, weight: f64) -> Self {
+        Self::new(a, b, Box::new(SolidColor::new(Vec3::one() * weight)))
+    }
+
+    fn weight(&self, hit_record: &HitRecord) -> f64 {
+        self.weight
+            .value(hit_record.u, hit_record.v, &hit_record.p)
+            .x()
+            .clamp(0.0, 1.0)
     }
 }
 
+impl<A: Material, B: Material> Material for MixMaterial<A, B> {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let mut rng = rand::thread_rng();
+        if rng.gen_range(0.0..1.0) < self.weight(hit_record) {
+            self.b.scatter(r_in, hit_record)
+        } else {
+            self.a.scatter(r_in, hit_record)
+        }
+    }
+
+    fn eval(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        let w = self.weight(hit_record);
+        self.a.eval(r_in, hit_record, direction) * (1.0 - w)
+            + self.b.eval(r_in, hit_record, direction) * w
+    }
+
+    fn pdf(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let w = self.weight(hit_record);
+        self.a.pdf(r_in, hit_record, direction) * (1.0 - w)
+            + self.b.pdf(r_in, hit_record, direction) * w
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Vec3 {
+        let w = self.weight(hit_record);
+        self.a.emitted(hit_record) * (1.0 - w) + self.b.emitted(hit_record) * w
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.a.is_emissive() || self.b.is_emissive()
+    }
+
+    fn set_emitting_area(&self, area: f64) {
+        self.a.set_emitting_area(area);
+        self.b.set_emitting_area(area);
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.a.is_dispersive() || self.b.is_dispersive()
+    }
+
+    fn interior_absorption(&self) -> Option<Vec3> {
+        self.a
+            .interior_absorption()
+            .or(self.b.interior_absorption())
+    }
+}
+
+/// Area light. By default `emit` is the emitted radiance from both sides.
+///
+/// With `normalize_by_area` the intensity is instead the total power in
+/// watts, spread over the surface, so resizing the light keeps its brightness.
+/// `Scene::new` sets the area from the object the light is on; until then the
+/// surface is taken to have unit area.
 pub struct DiffuseLight {
     emit: Box<dyn Texture>,
+    intensity: f64,
+    two_sided: bool,
+    normalize_by_area: bool,
+    area: OnceLock<f64>,
 }
 
 impl DiffuseLight {
     pub fn new(emit: Box<dyn Texture>) -> Self {
-        Self { emit }
+        Self {
+            emit,
+            intensity: 1.0,
+            two_sided: true,
+            normalize_by_area: false,
+            area: OnceLock::new(),
+        }
+    }
+
+    /// Colour of an ideal blackbody at `kelvin`, with a luminance of `radiance`.
+    pub fn blackbody(kelvin: f64, radiance: f64) -> Self {
+        let color = SolidColor::new(spectrum::blackbody_rgb(kelvin));
+        Self::new(Box::new(color)).with_intensity(radiance)
+    }
+
+    /// Emits `watts` in total from the surface it is on, tinted by `emit`.
+    pub fn from_power(emit: Box<dyn Texture>, watts: f64) -> Self {
+        Self::new(emit)
+            .with_intensity(watts)
+            .with_normalize_by_area(true)
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_two_sided(mut self, two_sided: bool) -> Self {
+        self.two_sided = two_sided;
+        self
+    }
+
+    pub fn with_normalize_by_area(mut self, normalize_by_area: bool) -> Self {
+        self.normalize_by_area = normalize_by_area;
+        self
+    }
+
+    fn radiance_scale(&self) -> f64 {
+        if !self.normalize_by_area {
+            return self.intensity;
+        }
+        let area = self.area.get().copied().unwrap_or(1.0);
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        // A Lambertian emitter radiates pi * L per unit area and side.
+        self.intensity / (std::f64::consts::PI * area * sides)
     }
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
         None
     }
 
-    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
-        self.emit.value(u, v, p)
+    fn emitted(&self, hit_record: &HitRecord) -> Vec3 {
+        if !self.two_sided && !hit_record.front_face {
+            return Vec3::zero();
+        }
+        self.emit.value(hit_record.u, hit_record.v, &hit_record.p) * self.radiance_scale()
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+
+    /// Keeps the first area it is told, as a light lies on one surface.
+    fn set_emitting_area(&self, area: f64) {
+        let _ = self.area.set(area);
     }
 }
 
@@ -141,9 +712,268 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
         let scattered = Ray::new(hit_record.p, Vec3::random_in_unit_sphere(), r_in.time);
         let attenuation = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
-        Some((scattered, attenuation))
+        Some(ScatterRecord::new(scattered, attenuation, false))
+    }
+
+    fn eval(&self, _r_in: &Ray, hit_record: &HitRecord, _direction: Vec3) -> Vec3 {
+        self.albedo.value(hit_record.u, hit_record.v, &hit_record.p) / (4.0 * PI)
+    }
+
+    fn pdf(&self, _r_in: &Ray, _hit_record: &HitRecord, _direction: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+}
+
+/// Scattering inside participating media with an arbitrary phase function.
+pub struct Volumetric {
+    albedo: Box<dyn Texture>,
+    phase: Box<dyn PhaseFunction>,
+}
+
+impl Volumetric {
+    pub fn new(albedo: Box<dyn Texture>, phase: Box<dyn PhaseFunction>) -> Self {
+        Self { albedo, phase }
+    }
+}
+
+impl Material for Volumetric {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let direction = self.phase.sample(r_in.direction);
+        let scattered = Ray::new(hit_record.p, direction, r_in.time);
+        let attenuation = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+        Some(ScatterRecord::new(scattered, attenuation, false))
+    }
+
+    fn eval(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        let albedo = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+        albedo * self.phase.eval(r_in.direction, direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, _hit_record: &HitRecord, direction: Vec3) -> f64 {
+        self.phase.eval(r_in.direction, direction)
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+}
+
+/// Participating medium that glows, like fire or a nebula. `emission` is the
+/// radiance of the emitting particles and `albedo` the probability of
+/// scattering rather than absorbing at a collision.
+///
+/// Only the absorbed fraction emits, so with the collision estimator every
+/// medium collision adds `(1 - albedo) * emission`; integrated over the free
+/// flight distribution that is the emission accumulated along the ray.
+pub struct EmissiveVolume {
+    albedo: Box<dyn Texture>,
+    emission: Box<dyn Texture>,
+    phase: Box<dyn PhaseFunction>,
+}
+
+impl EmissiveVolume {
+    pub fn new(
+        albedo: Box<dyn Texture>,
+        emission: Box<dyn Texture>,
+        phase: Box<dyn PhaseFunction>,
+    ) -> Self {
+        Self {
+            albedo,
+            emission,
+            phase,
+        }
+    }
+
+    /// Purely absorbing gas glowing as a blackbody at the temperature given
+    /// by `temperature` times `kelvin_scale`, see `BlackbodyTexture`.
+    pub fn blackbody(temperature: Box<dyn Texture>, kelvin_scale: f64, intensity: f64) -> Self {
+        let emission = BlackbodyTexture::new(temperature, kelvin_scale, intensity);
+        Self::new(
+            Box::new(SolidColor::new(Vec3::zero())),
+            Box::new(emission),
+            Box::new(IsotropicPhase),
+        )
+    }
+}
+
+impl Material for EmissiveVolume {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let attenuation = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+        if attenuation.x() + attenuation.y() + attenuation.z() <= 0.0 {
+            return None;
+        }
+        let direction = self.phase.sample(r_in.direction);
+        let scattered = Ray::new(hit_record.p, direction, r_in.time);
+        Some(ScatterRecord::new(scattered, attenuation, false))
+    }
+
+    fn eval(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        let albedo = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+        albedo * self.phase.eval(r_in.direction, direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, _hit_record: &HitRecord, direction: Vec3) -> f64 {
+        self.phase.eval(r_in.direction, direction)
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Vec3 {
+        let albedo = self.albedo.value(hit_record.u, hit_record.v, &hit_record.p);
+        let emission = self
+            .emission
+            .value(hit_record.u, hit_record.v, &hit_record.p);
+        (Vec3::one() - albedo) * emission
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ray along `direction` hitting the plane `y = 0` at the origin from
+    /// above.
+    fn hit_plane(material: &dyn Material, direction: Vec3) -> (Ray, HitRecord<'_>) {
+        let direction = Vec3::unit_vector(direction);
+        let r = Ray::new(-direction, direction, 0.0);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let rec = HitRecord::new(Vec3::zero(), 1.0, 0.0, 0.0, normal, r, material);
+        (r, rec)
+    }
+
+    #[test]
+    fn test_dispersion_follows_snell() {
+        let ior = RefractiveIndex::bk7();
+        let glass = Dielectric::dispersive(ior, 0.0);
+        let direction = Vec3::unit_vector(Vec3::new(1.0, -1.0, 0.0));
+        let sin_out = |lambda: f64| loop {
+            let (mut r, rec) = hit_plane(&glass, direction);
+            r.wavelength = Some(lambda);
+            let srec = glass.scatter(&r, &rec).unwrap();
+            assert_eq!(srec.scattered.wavelength, Some(lambda));
+            let d = Vec3::unit_vector(srec.scattered.direction);
+            if d.y() < 0.0 {
+                return d.x();
+            }
+        };
+        for lambda in [450.0, 550.0, 650.0] {
+            assert!((sin_out(lambda) - direction.x() / ior.at(lambda)).abs() < 1e-9);
+        }
+        assert!(sin_out(450.0) < sin_out(650.0));
+
+        // RGB rays pick a wavelength of their own on the way through.
+        let (r, rec) = hit_plane(&glass, direction);
+        assert!(glass
+            .scatter(&r, &rec)
+            .unwrap()
+            .scattered
+            .wavelength
+            .is_some());
+    }
+
+    #[test]
+    fn test_rough_dielectric_only_loses_energy() {
+        let glass = Dielectric::rough(1.5, 0.4);
+        let (r, rec) = hit_plane(&glass, Vec3::new(0.4, -1.0, 0.0));
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            if let Some(srec) = glass.scatter(&r, &rec) {
+                let weight = srec.attenuation.x();
+                assert!(weight <= 1.0 + 1e-9);
+                sum += weight;
+            }
+        }
+        let mean = sum / n as f64;
+        assert!(mean > 0.9 && mean <= 1.0, "{}", mean);
+    }
+
+    fn white() -> Lambertian {
+        Lambertian::new(Box::new(SolidColor::new(Vec3::one())))
+    }
+
+    #[test]
+    fn test_coat_conserves_energy() {
+        let clear = Coated::new(white(), 1.5);
+        let tinted = Coated::tinted(white(), 1.5, Vec3::new(0.9, 0.5, 0.1), 0.5);
+        let (r, rec) = hit_plane(&clear, Vec3::new(0.5, -1.0, 0.0));
+        let n = 100000;
+        let (mut total, mut base, mut base_eval) = (Vec3::zero(), Vec3::zero(), Vec3::zero());
+        for _ in 0..n {
+            let srec = clear.scatter(&r, &rec).unwrap();
+            total += srec.attenuation / n as f64;
+            if !srec.is_specular {
+                let wi = srec.scattered.direction;
+                base += srec.attenuation / n as f64;
+                base_eval += clear.eval(&r, &rec, wi) / clear.pdf(&r, &rec, wi) / n as f64;
+            }
+        }
+        // Only light reflected back into the coat at the exit is lost.
+        assert!(total.x() < 1.0 && total.x() > 0.85, "{}", total.x());
+        assert!((base.x() - base_eval.x()).abs() < 0.01);
+
+        let mut tinted_total = Vec3::zero();
+        for _ in 0..n {
+            tinted_total += tinted.scatter(&r, &rec).unwrap().attenuation / n as f64;
+        }
+        assert!(tinted_total.x() > tinted_total.y() && tinted_total.y() > tinted_total.z());
+        assert!(tinted_total.x() < total.x());
+    }
+
+    #[test]
+    fn test_mix_blends_by_weight() {
+        let red = Vec3::new(0.8, 0.2, 0.2);
+        let blue = Vec3::new(0.2, 0.2, 0.8);
+        let diffuse = || Lambertian::new(Box::new(SolidColor::new(red)));
+        let mix = MixMaterial::constant(diffuse(), Metal::new(blue, 0.0), 0.25);
+        let (r, rec) = hit_plane(&mix, Vec3::new(0.5, -1.0, 0.0));
+        let n = 40000;
+        let (mut mean, mut specular) = (Vec3::zero(), 0.0);
+        for _ in 0..n {
+            let srec = mix.scatter(&r, &rec).unwrap();
+            mean += srec.attenuation / n as f64;
+            if srec.is_specular {
+                specular += 1.0 / n as f64;
+            }
+        }
+        assert!((specular - 0.25).abs() < 0.01, "{}", specular);
+        let expected = red * 0.75 + blue * 0.25;
+        assert!((mean - expected).length() < 0.01);
+
+        // The mirror has no lobe for light sampling, so only the diffuse
+        // part is left, scaled by its share.
+        let direction = Vec3::new(0.3, 1.0, 0.2);
+        let lambertian = diffuse();
+        let f = lambertian.eval(&r, &rec, direction);
+        let pdf = lambertian.pdf(&r, &rec, direction);
+        assert!((mix.eval(&r, &rec, direction) - f * 0.75).length() < 1e-12);
+        assert!((mix.pdf(&r, &rec, direction) - pdf * 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_blackbody_volume_glows_with_temperature() {
+        let gas = |kelvin: f64| {
+            let temperature = Box::new(SolidColor::new(Vec3::one() * kelvin));
+            EmissiveVolume::blackbody(temperature, 1.0, 2.0)
+        };
+        let glow = |kelvin: f64| {
+            let volume = gas(kelvin);
+            let (r, rec) = hit_plane(&volume, Vec3::new(0.0, -1.0, 0.0));
+            assert!(volume.scatter(&r, &rec).is_none());
+            volume.emitted(&rec)
+        };
+        assert!((spectrum::luminance(glow(1000.0)) - 2.0).abs() < 1e-9);
+        assert!((spectrum::luminance(glow(2000.0)) - 32.0).abs() < 1e-9);
+        assert_eq!(glow(10.0), Vec3::zero());
+        assert_eq!(glow(f64::INFINITY), Vec3::zero());
     }
 }
@@ -0,0 +1,195 @@
+//! GGX (Trowbridge-Reitz) microfacet helpers. All directions are given in the
+//! local shading frame, where the macro surface normal is `+z`.
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+/// Smallest alpha we trace with, below this the lobe is numerically a mirror.
+pub const MIN_ALPHA: f64 = 1e-3;
+
+/// Maps the artist facing roughness in `[0, 1]` to the GGX alpha.
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    let r = roughness.clamp(0.0, 1.0);
+    f64::max(r * r, MIN_ALPHA)
+}
+
+pub fn ggx_d(m: Vec3, alpha: f64) -> f64 {
+    if m.z() <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let cos2 = m.z() * m.z();
+    let t = cos2 * (a2 - 1.0) + 1.0;
+    a2 / (PI * t * t)
+}
+
+fn smith_lambda(v: Vec3, alpha: f64) -> f64 {
+    let cos2 = v.z() * v.z();
+    if cos2 == 0.0 {
+        return f64::INFINITY;
+    }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * (-1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+pub fn smith_g1(v: Vec3, alpha: f64) -> f64 {
+    1.0 / (1.0 + smith_lambda(v, alpha))
+}
+
+/// Height-correlated Smith masking-shadowing.
+pub fn smith_g2(wo: Vec3, wi: Vec3, alpha: f64) -> f64 {
+    1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha))
+}
+
+/// Samples a microfacet normal from the distribution of normals visible from
+/// `wo` (Heitz 2018). `wo` has to be in the upper hemisphere.
+pub fn sample_ggx_vndf(wo: Vec3, alpha: f64, u1: f64, u2: f64) -> Vec3 {
+    let vh = Vec3::unit_vector(Vec3::new(alpha * wo.x(), alpha * wo.y(), wo.z()));
+    let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if lensq > 0.0 {
+        Vec3::new(-vh.y(), vh.x(), 0.0) / lensq.sqrt()
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = Vec3::cross(vh, t1);
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    let nh = p1 * t1 + p2 * t2 + p3 * vh;
+    Vec3::unit_vector(Vec3::new(alpha * nh.x(), alpha * nh.y(), nh.z().max(1e-6)))
+}
+
+/// Density of `sample_ggx_vndf` returning `m`, with respect to solid angle of `m`.
+pub fn ggx_vndf_pdf(wo: Vec3, m: Vec3, alpha: f64) -> f64 {
+    if wo.z() <= 0.0 {
+        return 0.0;
+    }
+    smith_g1(wo, alpha) * Vec3::dot(wo, m).max(0.0) * ggx_d(m, alpha) / wo.z()
+}
+
+/// `D * G2 / (4 cos_o)`: the reflection from `wo` into `wi` times the cosine
+/// at `wi`, without the Fresnel term.
+pub fn reflection(wo: Vec3, wi: Vec3, alpha: f64) -> f64 {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return 0.0;
+    }
+    let m = Vec3::unit_vector(wo + wi);
+    ggx_d(m, alpha) * smith_g2(wo, wi, alpha) / (4.0 * wo.z())
+}
+
+/// Density of reflecting `wo` into `wi` about a normal from `sample_ggx_vndf`.
+pub fn reflection_pdf(wo: Vec3, wi: Vec3, alpha: f64) -> f64 {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return 0.0;
+    }
+    let m = Vec3::unit_vector(wo + wi);
+    ggx_vndf_pdf(wo, m, alpha) / (4.0 * Vec3::dot(wo, m))
+}
+
+/// Unpolarized Fresnel reflectance of a conductor with complex IOR `eta + ik`.
+pub fn fresnel_conductor(cos_i: f64, eta: Vec3, k: Vec3) -> Vec3 {
+    Vec3::new(
+        fresnel_conductor_channel(cos_i, eta.x(), k.x()),
+        fresnel_conductor_channel(cos_i, eta.y(), k.y()),
+        fresnel_conductor_channel(cos_i, eta.z(), k.z()),
+    )
+}
+
+fn fresnel_conductor_channel(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i.clamp(0.0, 1.0) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ggx_d_normalized() {
+        // The projected area of the microsurface equals the macro surface.
+        let alpha = 0.3;
+        let n = 2000;
+        let mut sum = 0.0;
+        for i in 0..n {
+            let cos_theta = (i as f64 + 0.5) / n as f64;
+            let m = Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+            sum += ggx_d(m, alpha) * cos_theta * 2.0 * PI / n as f64;
+        }
+        assert!((sum - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_vndf_sample_is_visible() {
+        let wo = Vec3::unit_vector(Vec3::new(0.6, -0.2, 0.4));
+        for i in 0..64 {
+            let m = sample_ggx_vndf(
+                wo,
+                0.5,
+                (i as f64 + 0.5) / 64.0,
+                (i * 37 % 64) as f64 / 64.0,
+            );
+            assert!(m.z() > 0.0);
+            assert!(Vec3::dot(wo, m) >= -1e-9);
+        }
+    }
+
+    #[test]
+    fn test_reflection_pdf_matches_sampling() {
+        // Integrates to the fraction of sampled reflections above the surface.
+        let (wo, alpha) = (Vec3::unit_vector(Vec3::new(0.5, 0.1, 0.6)), 0.4);
+        let n = 400;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let cos_theta = (i as f64 + 0.5) / n as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                integral += reflection_pdf(wo, wi, alpha) * 2.0 * PI / (n * n) as f64;
+            }
+        }
+        let m = 200;
+        let mut above = 0;
+        for i in 0..m {
+            for j in 0..m {
+                let h = sample_ggx_vndf(
+                    wo,
+                    alpha,
+                    (i as f64 + 0.5) / m as f64,
+                    (j as f64 + 0.5) / m as f64,
+                );
+                if Vec3::reflect(-wo, h).z() > 0.0 {
+                    above += 1;
+                }
+            }
+        }
+        assert!((integral - above as f64 / (m * m) as f64).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_fresnel_conductor_range() {
+        let eta = Vec3::new(0.143, 0.374, 1.442);
+        let k = Vec3::new(3.983, 2.385, 1.603);
+        let normal = fresnel_conductor(1.0, eta, k);
+        let grazing = fresnel_conductor(0.0, eta, k);
+        assert!(normal.x() > 0.9 && normal.z() < normal.x());
+        assert!((grazing.y() - 1.0).abs() < 1e-9);
+    }
+}
@@ -2,6 +2,7 @@ use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sphere::{sample_sphere, sphere_pdf};
 use crate::vec3::Vec3;
 
 pub struct MovingSphere<Material> {
@@ -39,7 +40,7 @@ impl<M: Material> MovingSphere<M> {
 }
 
 impl<M: Material> Hittable for MovingSphere<M> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = r.origin - self.center(r.time);
         let a = r.direction.squared_length();
         let half_b = Vec3::dot(oc, r.direction);
@@ -78,4 +79,32 @@ impl<M: Material> Hittable for MovingSphere<M> {
         let output_box = Aabb::surrounding_box(box0, box1);
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        match self.hit(Ray::new(origin, v, time), 0.001, f64::INFINITY) {
+            Some(rec) => sphere_pdf(self.center(time), self.radius, origin, v, &rec),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, time: f64) -> Vec3 {
+        sample_sphere(self.center(time), self.radius, origin)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let outward_normal = Vec3::random_unit_vector();
+        let p = self.center(time) + self.radius * outward_normal;
+        let (u, v) = MovingSphere::<M>::get_sphere_uv(&outward_normal);
+        let rec = HitRecord::on_surface(p, u, v, outward_normal, time, &self.mat_ptr);
+        let area = 4.0 * std::f64::consts::PI * self.radius * self.radius;
+        Some((rec, 1.0 / area))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(4.0 * std::f64::consts::PI * self.radius * self.radius)
+    }
 }
@@ -0,0 +1,165 @@
+//! Phase functions for participating media. `direction` is the direction the
+//! light is travelling in before the scattering event, so `cos_theta == 1` is
+//! forward scattering.
+use crate::onb::Onb;
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::f64::consts::PI;
+
+pub trait PhaseFunction: Send + Sync {
+    /// Samples a scattered direction, distributed proportionally to `eval`.
+    fn sample(&self, direction: Vec3) -> Vec3;
+
+    /// Phase function value, which is also the solid angle density of `sample`.
+    fn eval(&self, direction: Vec3, scattered: Vec3) -> f64;
+}
+
+fn cos_between(a: Vec3, b: Vec3) -> f64 {
+    Vec3::dot(Vec3::unit_vector(a), Vec3::unit_vector(b))
+}
+
+/// Direction at `cos_theta` from `direction`, with a uniformly random azimuth.
+fn direction_from_cos(direction: Vec3, cos_theta: f64) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let cos_theta = cos_theta.clamp(-1.0, 1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * PI * rng.gen::<f64>();
+    let uvw = Onb::build_from_w(direction);
+    uvw.local(Vec3::new(
+        sin_theta * phi.cos(),
+        sin_theta * phi.sin(),
+        cos_theta,
+    ))
+}
+
+pub struct IsotropicPhase;
+
+impl PhaseFunction for IsotropicPhase {
+    fn sample(&self, _direction: Vec3) -> Vec3 {
+        Vec3::random_unit_vector()
+    }
+
+    fn eval(&self, _direction: Vec3, _scattered: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// Henyey-Greenstein with asymmetry `g` in `(-1, 1)`, positive for the forward
+/// scattering of fog, clouds and smoke.
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self {
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+
+    fn value(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn sample(&self, direction: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let u = rng.gen::<f64>();
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let sqr = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            (1.0 + g * g - sqr * sqr) / (2.0 * g)
+        };
+        direction_from_cos(direction, cos_theta)
+    }
+
+    fn eval(&self, direction: Vec3, scattered: Vec3) -> f64 {
+        self.value(cos_between(direction, scattered))
+    }
+}
+
+/// Blend of a forward and a backward Henyey-Greenstein lobe, with `weight`
+/// going to the `forward` one.
+pub struct DoubleHenyeyGreenstein {
+    pub forward: HenyeyGreenstein,
+    pub backward: HenyeyGreenstein,
+    pub weight: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    pub fn new(g_forward: f64, g_backward: f64, weight: f64) -> Self {
+        Self {
+            forward: HenyeyGreenstein::new(g_forward),
+            backward: HenyeyGreenstein::new(g_backward),
+            weight: weight.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl PhaseFunction for DoubleHenyeyGreenstein {
+    fn sample(&self, direction: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        if rng.gen::<f64>() < self.weight {
+            self.forward.sample(direction)
+        } else {
+            self.backward.sample(direction)
+        }
+    }
+
+    fn eval(&self, direction: Vec3, scattered: Vec3) -> f64 {
+        self.weight * self.forward.eval(direction, scattered)
+            + (1.0 - self.weight) * self.backward.eval(direction, scattered)
+    }
+}
+
+/// Scattering by particles much smaller than the wavelength, e.g. clear air.
+pub struct Rayleigh;
+
+impl PhaseFunction for Rayleigh {
+    fn sample(&self, direction: Vec3) -> Vec3 {
+        // Invert the CDF (mu^3 + 3 mu + 4) / 8 with Cardano's formula.
+        let mut rng = rand::thread_rng();
+        let q = 4.0 - 8.0 * rng.gen::<f64>();
+        let d = (0.25 * q * q + 1.0).sqrt();
+        let cos_theta = f64::cbrt(-0.5 * q + d) + f64::cbrt(-0.5 * q - d);
+        direction_from_cos(direction, cos_theta)
+    }
+
+    fn eval(&self, direction: Vec3, scattered: Vec3) -> f64 {
+        let cos_theta = cos_between(direction, scattered);
+        3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mean_cosine(phase: &dyn PhaseFunction) -> f64 {
+        let direction = Vec3::new(0.0, 0.0, 1.0);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += cos_between(direction, phase.sample(direction));
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn test_henyey_greenstein_asymmetry() {
+        assert!((mean_cosine(&HenyeyGreenstein::new(0.7)) - 0.7).abs() < 0.03);
+        assert!((mean_cosine(&HenyeyGreenstein::new(-0.3)) + 0.3).abs() < 0.03);
+    }
+
+    #[test]
+    fn test_rayleigh_symmetric() {
+        assert!(mean_cosine(&Rayleigh).abs() < 0.03);
+        let forward = Rayleigh.eval(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!((forward - 3.0 / (8.0 * PI)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,368 @@
+//! Photon mapping for caustics: light is traced from the emissive objects
+//! through glass and mirrors, and the photons it leaves on the surfaces behind
+//! them are looked up by position when shading those surfaces. Photons are
+//! only sent towards the glass and mirrors, since the rest of the light is
+//! found just as well by the path tracer.
+use crate::hittable::HitRecord;
+use crate::integrator::continue_path;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Bounces a photon may take through specular surfaces.
+const MAX_PHOTON_BOUNCES: usize = 16;
+
+/// Photons emitted per photon asked for before giving up, for scenes where
+/// the specular objects are hidden from the lights.
+const MAX_EMITTED_PER_PHOTON: usize = 1000;
+
+/// Flux arriving at a point on a surface.
+#[derive(Clone, Copy)]
+pub struct Photon {
+    pub position: Vec3,
+    /// Direction the photon was travelling in, normalized.
+    pub direction: Vec3,
+    pub power: Vec3,
+}
+
+fn coordinate(v: Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x(),
+        1 => v.y(),
+        _ => v.z(),
+    }
+}
+
+/// Arranges `photons` into a balanced kd-tree: the median along the axis of
+/// largest extent goes to the middle, with the photons below it on the left
+/// and the rest on the right, each half arranged the same way. `axes` records
+/// the axis each median splits on.
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let mut min = photons[0].position;
+    let mut max = min;
+    for photon in photons.iter() {
+        let p = photon.position;
+        min = Vec3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z()));
+        max = Vec3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()));
+    }
+    let extent = max - min;
+    let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+        0
+    } else if extent.y() >= extent.z() {
+        1
+    } else {
+        2
+    };
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| {
+        let a = coordinate(a.position, axis);
+        let b = coordinate(b.position, axis);
+        a.total_cmp(&b)
+    });
+    axes[mid] = axis;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+/// Calls `f` for every photon in the kd-tree `photons` within
+/// `sqrt(radius_squared)` of `p`.
+fn visit(
+    photons: &[Photon],
+    axes: &[usize],
+    p: Vec3,
+    radius_squared: f64,
+    f: &mut impl FnMut(&Photon),
+) {
+    if photons.is_empty() {
+        return;
+    }
+    let mid = photons.len() / 2;
+    let photon = &photons[mid];
+    if (photon.position - p).squared_length() <= radius_squared {
+        f(photon);
+    }
+    let delta = coordinate(p, axes[mid]) - coordinate(photon.position, axes[mid]);
+    let left = (&photons[..mid], &axes[..mid]);
+    let right = (&photons[mid + 1..], &axes[mid + 1..]);
+    let (near, far) = if delta < 0.0 {
+        (left, right)
+    } else {
+        (right, left)
+    };
+    visit(near.0, near.1, p, radius_squared, f);
+    if delta * delta <= radius_squared {
+        visit(far.0, far.1, p, radius_squared, f);
+    }
+}
+
+/// Objects in `scene.world` that photons are aimed at: those with nothing but
+/// specular lobes, judged by a point sampled on them, such as glass and
+/// polished metal. Objects that cannot be sampled are left out, and a group
+/// mixing glass with other surfaces may go either way. Light the map misses
+/// this way is still found by the path tracer, see `PhotonMap::covers`.
+fn caustic_casters(scene: &Scene) -> Vec<usize> {
+    (0..scene.world.hittable_list.len())
+        .filter(|&i| {
+            let Some((rec, _)) = scene.world.hittable_list[i].sample_surface(0.0) else {
+                return false;
+            };
+            let r = Ray::new(rec.p + rec.normal, -rec.normal, 0.0);
+            let material = rec.mat_ptr;
+            !material.is_emissive()
+                && !material.is_volumetric()
+                && material.eval(&r, &rec, rec.normal) == Vec3::zero()
+        })
+        .collect()
+}
+
+/// Photon leaving a point picked uniformly on a light picked uniformly,
+/// towards a point on one of `casters` picked uniformly, and its power.
+fn emit_photon(scene: &Scene, casters: &[usize], time: f64) -> Option<(Ray, Vec3)> {
+    let mut rng = rand::thread_rng();
+    let objects = &scene.world.hittable_list;
+    let light = scene.lights[rng.gen_range(0..scene.lights.len())];
+    let (rec, area_pdf) = objects[light].sample_surface(time)?;
+    let caster = casters[rng.gen_range(0..casters.len())];
+    let direction = Vec3::unit_vector(objects[caster].random(rec.p, time));
+    let pdf_direction = casters
+        .iter()
+        .map(|&i| objects[i].pdf_value(rec.p, direction, time))
+        .sum::<f64>()
+        / casters.len() as f64;
+    if pdf_direction <= 0.0 {
+        return None;
+    }
+
+    let rec = if Vec3::dot(direction, rec.normal) >= 0.0 {
+        rec
+    } else {
+        rec.flipped()
+    };
+    let cosine = Vec3::dot(direction, rec.normal);
+    let pdf_position = area_pdf / scene.lights.len() as f64;
+    let power = rec.mat_ptr.emitted(&rec) * (cosine / (pdf_position * pdf_direction));
+    Some((Ray::new(rec.p, direction, time), power))
+}
+
+/// Follows a photon leaving a light along `r` with `power` through specular
+/// bounces, storing it on every surface it lands on after the first of them.
+/// Surfaces with nothing but specular lobes, which `eval` is zero for, and
+/// media are left to the path tracer.
+fn trace_photon(scene: &Scene, mut r: Ray, mut power: Vec3, photons: &mut Vec<Photon>) {
+    let mut specular = false;
+    for _ in 0..MAX_PHOTON_BOUNCES {
+        let Some(rec) = scene.hit(&r, 0.001, f64::INFINITY) else {
+            return;
+        };
+        if rec.mat_ptr.is_volumetric() {
+            return;
+        }
+        power = power * r.media.transmittance(rec.t * r.direction.length());
+        if specular && rec.mat_ptr.eval(&r, &rec, rec.normal) != Vec3::zero() {
+            photons.push(Photon {
+                position: rec.p,
+                direction: Vec3::unit_vector(r.direction),
+                power,
+            });
+        }
+        let Some(mut srec) = rec.mat_ptr.scatter(&r, &rec) else {
+            return;
+        };
+        if !srec.is_specular {
+            return;
+        }
+        specular = true;
+        continue_path(&r, &rec, &mut srec.scattered);
+        power = power * srec.attenuation;
+        r = srec.scattered;
+    }
+}
+
+/// Photons in a kd-tree, with the radius radiance is estimated over and the
+/// objects in the world they were aimed at.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+    casters: Vec<usize>,
+    pub radius: f64,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>, radius: f64) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self {
+            photons,
+            axes,
+            casters: Vec::new(),
+            radius,
+        }
+    }
+
+    /// Caustic photon map of `scene`: photons leave the emissive objects
+    /// towards the specular ones, at times spread over the shutter interval
+    /// `[0, 1)`, until about `photons` of them have landed on surfaces after
+    /// passing through or off glass and mirrors. The map only fits scenes
+    /// with the same world as `scene`.
+    pub fn caustics(scene: &Scene, photons: usize, radius: f64) -> Self {
+        let mut rng = rand::thread_rng();
+        let casters = caustic_casters(scene);
+        let mut stored = Vec::with_capacity(photons);
+        let mut emitted = 0;
+        if !scene.lights.is_empty() && !casters.is_empty() {
+            while stored.len() < photons && emitted < photons * MAX_EMITTED_PER_PHOTON {
+                emitted += 1;
+                if let Some((r, power)) = emit_photon(scene, &casters, rng.gen()) {
+                    trace_photon(scene, r, power, &mut stored);
+                }
+            }
+        }
+        for photon in stored.iter_mut() {
+            photon.power /= emitted as f64;
+        }
+        Self {
+            casters,
+            ..Self::new(stored, radius)
+        }
+    }
+
+    /// Whether the light seen at `hit_record` on a light along `r`, after
+    /// specular bounces from a surface gathering from the map, is in the map
+    /// already: photons leaving there back along `r` are aimed at a caster.
+    pub fn covers(&self, scene: &Scene, r: &Ray, hit_record: &HitRecord) -> bool {
+        let objects = &scene.world.hittable_list;
+        self.casters
+            .iter()
+            .any(|&i| objects[i].pdf_value(hit_record.p, -r.direction, r.time) > 0.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Calls `f` for every photon within `radius` of `p`.
+    pub fn within(&self, p: Vec3, radius: f64, mut f: impl FnMut(&Photon)) {
+        visit(&self.photons, &self.axes, p, radius * radius, &mut f);
+    }
+
+    /// Radiance the photons around `hit_record` reflect back along `r`: the
+    /// flux of those within `radius` on the side facing `r`, spread over a
+    /// disc of that radius.
+    pub fn estimate(&self, r: &Ray, hit_record: &HitRecord) -> Vec3 {
+        let mut flux = Vec3::zero();
+        self.within(hit_record.p, self.radius, |photon| {
+            let direction = -photon.direction;
+            let cosine = Vec3::dot(direction, hit_record.normal);
+            if cosine > 0.0 {
+                let f = hit_record.mat_ptr.eval(r, hit_record, direction) / cosine;
+                flux += f * photon.power;
+            }
+        });
+        flux / (PI * self.radius * self.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XzRect;
+    use crate::hittable_list::HittableList;
+    use crate::integrator::{Integrator, PathTracer, RenderStats, SplatBuffer};
+    use crate::material::{Dielectric, DiffuseLight, Lambertian};
+    use crate::sphere::Sphere;
+    use crate::subsurface::Subsurface;
+    use crate::texture::SolidColor;
+
+    #[test]
+    fn test_photons_within_radius() {
+        let photons: Vec<Photon> = (0..500)
+            .map(|_| Photon {
+                position: Vec3::random_(-1.0, 1.0),
+                direction: Vec3::new(0.0, -1.0, 0.0),
+                power: Vec3::one(),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone(), 0.3);
+        assert_eq!(map.len(), photons.len());
+
+        for _ in 0..20 {
+            let p = Vec3::random_(-1.0, 1.0);
+            let mut found = Vec::new();
+            map.within(p, map.radius, |photon| found.push(photon.position));
+            let mut expected: Vec<Vec3> = photons
+                .iter()
+                .map(|photon| photon.position)
+                .filter(|&q| (q - p).squared_length() <= 0.09)
+                .collect();
+            let key = |v: &Vec3| (v.x(), v.y(), v.z());
+            found.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            expected.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            assert_eq!(found.len(), expected.len());
+            for (a, b) in found.iter().zip(&expected) {
+                assert_eq!(key(a), key(b));
+            }
+        }
+    }
+
+    /// A floor lit through two glass balls: one photons are aimed at, and one
+    /// that cannot be sampled, whose light is left to the path tracer.
+    fn glass_balls() -> Scene {
+        let white = || Box::new(SolidColor::new(Vec3::one()));
+        let mut world = HittableList::new();
+        let floor = Lambertian::new(Box::new(SolidColor::new(Vec3::one() * 0.5)));
+        world.add(Box::new(XzRect::new(-10.0, 10.0, -10.0, 10.0, 0.0, floor)));
+        let light = DiffuseLight::new(white()).with_intensity(4.0);
+        world.add(Box::new(XzRect::new(-1.0, 1.0, -1.0, 1.0, 5.0, light)));
+        let glass = Dielectric::new(1.5);
+        world.add(Box::new(Sphere::new(Vec3::new(-1.5, 1.5, 0.0), 0.8, glass)));
+        let ball = Sphere::new(Vec3::new(1.5, 1.5, 0.0), 0.8, Lambertian::new(white()));
+        let clear = Subsurface::new(Box::new(ball), 1e9, Vec3::one(), 1.5);
+        world.add(Box::new(clear));
+        Scene::new(world, Vec3::zero())
+    }
+
+    /// Mean radiance leaving the floor below the balls.
+    fn floor_radiance(scene: &Scene) -> f64 {
+        let tracer = PathTracer::new(20);
+        let mut splats = SplatBuffer::new(1, 1);
+        let mut stats = RenderStats::default();
+        let n = 100000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let x = rand::thread_rng().gen_range(-3.0..3.0);
+            let z = rand::thread_rng().gen_range(-1.5..1.5);
+            let r = Ray::new(Vec3::new(x, 0.5, z), Vec3::new(0.0, -1.0, 0.0), 0.0);
+            sum += tracer.radiance(&r, scene, &mut splats, &mut stats).x();
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn test_caustics_match_path_tracing() {
+        let scene = glass_balls();
+        let caustics = PhotonMap::caustics(&scene, 100000, 0.1);
+        assert_eq!(caustics.casters, vec![2]);
+        let expected = floor_radiance(&scene);
+        let scene = glass_balls().with_caustics(caustics);
+        let radiance = floor_radiance(&scene);
+        let ratio = radiance / expected;
+        assert!(
+            (ratio - 1.0).abs() < 0.05,
+            "{} against {}",
+            radiance,
+            expected
+        );
+    }
+}
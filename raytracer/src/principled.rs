@@ -0,0 +1,363 @@
+use crate::hittable::HitRecord;
+use crate::material::{Dielectric, Material, ScatterRecord};
+use crate::microfacet;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::spectrum::luminance;
+use crate::texture::{SolidColor, Texture};
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// The clear coat is a fixed, glossy GGX lobe on top of everything else.
+const CLEARCOAT_ALPHA: f64 = 0.05;
+
+fn constant(x: f64) -> Box<dyn Texture> {
+    Box::new(SolidColor::new(Vec3::new(x, x, x)))
+}
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+fn schlick(f0: Vec3, cosine: f64) -> Vec3 {
+    f0 + (Vec3::one() - f0) * schlick_weight(cosine)
+}
+
+fn pick_lobe(probabilities: &[f64; 4], u: f64) -> usize {
+    let mut cdf = 0.0;
+    for (i, p) in probabilities.iter().enumerate() {
+        cdf += p;
+        if u < cdf {
+            return i;
+        }
+    }
+    probabilities.len() - 1
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Disney-style uber material. Every parameter is a texture, scalar parameters
+/// read the first channel.
+///
+/// The lobes (diffuse with sheen and a subsurface approximation, specular,
+/// transmission and clear coat) are chosen stochastically per scatter, each
+/// sample being weighted by its own lobe divided by the selection probability.
+pub struct Principled {
+    pub base_color: Box<dyn Texture>,
+    pub metallic: Box<dyn Texture>,
+    pub roughness: Box<dyn Texture>,
+    pub specular: Box<dyn Texture>,
+    pub sheen: Box<dyn Texture>,
+    pub clearcoat: Box<dyn Texture>,
+    pub transmission: Box<dyn Texture>,
+    pub subsurface: Box<dyn Texture>,
+    pub ior: Box<dyn Texture>,
+}
+
+/// Texture values of a `Principled` at one hit point.
+struct Parameters {
+    base_color: Vec3,
+    metallic: f64,
+    roughness: f64,
+    alpha: f64,
+    specular_f0: Vec3,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    subsurface: f64,
+    ior: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Box<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            transmission: constant(0.0),
+            subsurface: constant(0.0),
+            ior: constant(1.5),
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: Box<dyn Texture>) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: Box<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn with_specular(mut self, specular: Box<dyn Texture>) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    pub fn with_sheen(mut self, sheen: Box<dyn Texture>) -> Self {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: Box<dyn Texture>) -> Self {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: Box<dyn Texture>) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    pub fn with_ior(mut self, ior: Box<dyn Texture>) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    pub fn with_subsurface(mut self, subsurface: Box<dyn Texture>) -> Self {
+        self.subsurface = subsurface;
+        self
+    }
+
+    /// `Parameters::reflection` at the hit, zero where `scatter` falls back
+    /// to a plain dielectric.
+    fn reflection(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> (Vec3, f64) {
+        let params = self.parameters(hit_record);
+        if !hit_record.front_face && params.transmission > 0.0 {
+            return (Vec3::zero(), 0.0);
+        }
+        let uvw = Onb::build_from_w(hit_record.normal);
+        let wo = uvw.to_local(-Vec3::unit_vector(r_in.direction));
+        let wi = uvw.to_local(Vec3::unit_vector(direction));
+        params.reflection(wo, wi)
+    }
+
+    fn parameters(&self, hit_record: &HitRecord) -> Parameters {
+        let (u, v, p) = (hit_record.u, hit_record.v, &hit_record.p);
+        let base_color = self.base_color.value(u, v, p);
+        let metallic = self.metallic.value(u, v, p).x().clamp(0.0, 1.0);
+        let roughness = self.roughness.value(u, v, p).x().clamp(0.0, 1.0);
+        let specular = self.specular.value(u, v, p).x().max(0.0);
+        // Disney maps specular 0.5 to the 4% reflectance of common dielectrics.
+        let dielectric_f0 = Vec3::one() * (0.08 * specular);
+        Parameters {
+            base_color,
+            metallic,
+            roughness,
+            alpha: microfacet::roughness_to_alpha(roughness),
+            specular_f0: dielectric_f0 * (1.0 - metallic) + base_color * metallic,
+            sheen: self.sheen.value(u, v, p).x().max(0.0),
+            clearcoat: self.clearcoat.value(u, v, p).x().clamp(0.0, 1.0),
+            transmission: self.transmission.value(u, v, p).x().clamp(0.0, 1.0),
+            subsurface: self.subsurface.value(u, v, p).x().clamp(0.0, 1.0),
+            ior: self.ior.value(u, v, p).x().max(1.0),
+        }
+    }
+}
+
+impl Parameters {
+    /// Probabilities of picking the diffuse, specular, transmission and clear
+    /// coat lobes. Every lobe that can contribute gets a non-zero share.
+    fn lobe_probabilities(&self, cos_o: f64) -> [f64; 4] {
+        let fresnel = luminance(schlick(self.specular_f0, cos_o)).clamp(0.0, 1.0);
+        let dielectric = 1.0 - self.metallic;
+        let diffuse = dielectric * (1.0 - self.transmission) * (1.0 - fresnel);
+        let specular = 1.0 - dielectric * (1.0 - fresnel);
+        let transmission = dielectric * self.transmission * (1.0 - fresnel);
+        let clearcoat = 0.25 * self.clearcoat * (0.04 + 0.96 * schlick_weight(cos_o));
+        let total = diffuse + specular + transmission + clearcoat;
+        [
+            diffuse / total,
+            specular / total,
+            transmission / total,
+            clearcoat / total,
+        ]
+    }
+
+    /// Diffuse, retro-reflection, subsurface flattening and sheen, without cosine.
+    fn diffuse(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        let h = Vec3::unit_vector(wo + wi);
+        let cos_d = Vec3::dot(wi, h);
+        let fl = schlick_weight(wi.z());
+        let fv = schlick_weight(wo.z());
+
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+
+        // Hanrahan-Krueger inspired flattening used by Disney for subsurface.
+        let fss90 = self.roughness * cos_d * cos_d;
+        let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+        let ss = 1.25 * (fss * (1.0 / (wi.z() + wo.z()) - 0.5) + 0.5);
+
+        let sheen = Vec3::one() * (self.sheen * schlick_weight(cos_d));
+        let weight = (1.0 - self.metallic) * (1.0 - self.transmission);
+        (self.base_color * (lerp(fd, ss, self.subsurface) / PI) + sheen) * weight
+    }
+
+    /// Diffuse, specular and clear coat reflection from `wo` into `wi` times
+    /// the cosine, with the density of `scatter` picking `wi` through them. A
+    /// mirror-like specular lobe is left out, like the transmission.
+    fn reflection(&self, wo: Vec3, wi: Vec3) -> (Vec3, f64) {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return (Vec3::zero(), 0.0);
+        }
+        let probabilities = self.lobe_probabilities(wo.z());
+        let cos_d = Vec3::dot(wo, Vec3::unit_vector(wo + wi));
+        let mut f = self.diffuse(wo, wi) * wi.z();
+        let mut pdf = probabilities[0] * wi.z() / PI;
+        if self.alpha > microfacet::MIN_ALPHA {
+            f += schlick(self.specular_f0, cos_d) * microfacet::reflection(wo, wi, self.alpha);
+            pdf += probabilities[1] * microfacet::reflection_pdf(wo, wi, self.alpha);
+        }
+        let clearcoat = 0.25 * self.clearcoat * microfacet::reflection(wo, wi, CLEARCOAT_ALPHA);
+        f += schlick(Vec3::one() * 0.04, cos_d) * clearcoat;
+        pdf += probabilities[3] * microfacet::reflection_pdf(wo, wi, CLEARCOAT_ALPHA);
+        (f, pdf)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let params = self.parameters(hit_record);
+        if !hit_record.front_face && params.transmission > 0.0 {
+            // Leaving the interior is a plain dielectric interface.
+            return Dielectric::rough(params.ior, params.roughness).scatter(r_in, hit_record);
+        }
+
+        let uvw = Onb::build_from_w(hit_record.normal);
+        let wo = uvw.to_local(-Vec3::unit_vector(r_in.direction));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let probabilities = params.lobe_probabilities(wo.z());
+        let mut rng = rand::thread_rng();
+        let lobe = pick_lobe(&probabilities, rng.gen());
+
+        let (wi, attenuation) = if lobe == 0 {
+            let mut wi = Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit_vector();
+            if wi.near_zero() {
+                wi = Vec3::new(0.0, 0.0, 1.0);
+            }
+            let wi = Vec3::unit_vector(wi);
+            // Cosine sampling cancels the cosine, leaving f * pi.
+            (wi, params.diffuse(wo, wi) * (PI / probabilities[0]))
+        } else if lobe == 1 {
+            let m = microfacet::sample_ggx_vndf(wo, params.alpha, rng.gen(), rng.gen());
+            let wi = Vec3::reflect(-wo, m);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let g =
+                microfacet::smith_g2(wo, wi, params.alpha) / microfacet::smith_g1(wo, params.alpha);
+            let fresnel = schlick(params.specular_f0, Vec3::dot(wo, m));
+            (wi, fresnel * (g / probabilities[1]))
+        } else if lobe == 2 {
+            let m = microfacet::sample_ggx_vndf(wo, params.alpha, rng.gen(), rng.gen());
+            let cos_theta = Vec3::dot(wo, m);
+            let wi = Vec3::refract(-wo, m, 1.0 / params.ior);
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            let g =
+                microfacet::smith_g2(wo, wi, params.alpha) / microfacet::smith_g1(wo, params.alpha);
+            let transmitted = 1.0 - Vec3::schlick(cos_theta, 1.0 / params.ior);
+            let weight = (1.0 - params.metallic) * params.transmission * transmitted * g;
+            (wi, params.base_color * (weight / probabilities[2]))
+        } else {
+            let m = microfacet::sample_ggx_vndf(wo, CLEARCOAT_ALPHA, rng.gen(), rng.gen());
+            let wi = Vec3::reflect(-wo, m);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            let g = microfacet::smith_g2(wo, wi, CLEARCOAT_ALPHA)
+                / microfacet::smith_g1(wo, CLEARCOAT_ALPHA);
+            let fresnel = schlick(Vec3::one() * 0.04, Vec3::dot(wo, m));
+            (
+                wi,
+                fresnel * (0.25 * params.clearcoat * g / probabilities[3]),
+            )
+        };
+
+        // Transmission and mirror reflection are not evaluated for light sampling.
+        let specular = lobe == 2 || (lobe == 1 && params.alpha <= microfacet::MIN_ALPHA);
+        let scattered = Ray::new(hit_record.p, uvw.local(wi), r_in.time);
+        Some(ScatterRecord::new(scattered, attenuation, specular))
+    }
+
+    fn eval(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> Vec3 {
+        self.reflection(r_in, hit_record, direction).0
+    }
+
+    fn pdf(&self, r_in: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        self.reflection(r_in, hit_record, direction).1
+    }
+
+    fn interior_absorption(&self) -> Option<Vec3> {
+        Some(Vec3::zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plastic() -> Principled {
+        Principled::new(constant(0.8))
+            .with_roughness(constant(0.4))
+            .with_sheen(constant(0.5))
+            .with_clearcoat(constant(1.0))
+    }
+
+    #[test]
+    fn test_reflection_is_reciprocal() {
+        let material = plastic();
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let rec = HitRecord::on_surface(Vec3::zero(), 0.0, 0.0, normal, 0.0, &material);
+        let params = material.parameters(&rec);
+        let a = Vec3::unit_vector(Vec3::new(0.3, -0.2, 0.9));
+        let b = Vec3::unit_vector(Vec3::new(-0.6, 0.1, 0.5));
+        let ab = params.diffuse(a, b);
+        let ba = params.diffuse(b, a);
+        assert!((ab - ba).length() < 1e-12);
+        // `reflection` includes the cosine at the outgoing direction.
+        let ab = params.reflection(a, b).0 / b.z();
+        let ba = params.reflection(b, a).0 / a.z();
+        assert!((ab - ba).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_eval_matches_sampling() {
+        // With every lobe glossy, the mean sampled weight and the mean of
+        // eval / pdf over the same directions both estimate the albedo.
+        let material = plastic();
+        let direction = Vec3::unit_vector(Vec3::new(0.5, 0.0, -1.0));
+        let r = Ray::new(-direction, direction, 0.0);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let rec = HitRecord::new(Vec3::zero(), 1.0, 0.0, 0.0, normal, r, &material);
+        let n = 200000;
+        let (mut sampled, mut evaluated) = (0.0, 0.0);
+        for _ in 0..n {
+            let Some(srec) = material.scatter(&r, &rec) else {
+                continue;
+            };
+            assert!(!srec.is_specular);
+            let wi = srec.scattered.direction;
+            sampled += srec.attenuation.x() / n as f64;
+            evaluated += material.eval(&r, &rec, wi).x() / material.pdf(&r, &rec, wi) / n as f64;
+        }
+        assert!(sampled < 1.0);
+        assert!(
+            (sampled - evaluated).abs() < 0.02 * sampled,
+            "{} {}",
+            sampled,
+            evaluated
+        );
+    }
+}
@@ -1,10 +1,78 @@
 use crate::vec3::Vec3;
 
+const MAX_NESTED_MEDIA: usize = 4;
+
+/// Absorption coefficients of the nested media a ray travels through, with the
+/// innermost on top. Surfaces with an interior push on entry and pop on exit.
+///
+/// Only the outermost `MAX_NESTED_MEDIA` media are stored, so the stack stays
+/// `Copy` along with `Ray`. Deeper media are still counted, keeping pushes and
+/// pops balanced, but take on the absorption of the deepest stored one.
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub struct MediumStack {
+    absorption: [Vec3; MAX_NESTED_MEDIA],
+    len: usize,
+}
+
+impl MediumStack {
+    pub fn new() -> Self {
+        Self {
+            absorption: [Vec3::zero(); MAX_NESTED_MEDIA],
+            len: 0,
+        }
+    }
+
+    /// Enters a medium, which is only remembered while there is room.
+    pub fn push(&mut self, absorption: Vec3) {
+        if self.len < MAX_NESTED_MEDIA {
+            self.absorption[self.len] = absorption;
+        }
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) {
+        self.len = self.len.saturating_sub(1);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn current(&self) -> Vec3 {
+        match self.len {
+            0 => Vec3::zero(),
+            n => self.absorption[n.min(MAX_NESTED_MEDIA) - 1],
+        }
+    }
+
+    /// Beer-Lambert transmittance of the current medium over `distance`.
+    pub fn transmittance(&self, distance: f64) -> Vec3 {
+        let sigma = self.current();
+        let channel = |s: f64| {
+            if s > 0.0 {
+                f64::exp(-s * distance)
+            } else {
+                1.0
+            }
+        };
+        Vec3::new(channel(sigma.x()), channel(sigma.y()), channel(sigma.z()))
+    }
+}
+
+impl Default for MediumStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub time: f64,
+    /// Set once a dispersive surface has collapsed the path to one wavelength (nm).
+    pub wavelength: Option<f64>,
+    pub media: MediumStack,
 }
 
 impl Ray {
@@ -13,6 +81,8 @@ impl Ray {
             origin,
             direction,
             time,
+            wavelength: None,
+            media: MediumStack::new(),
         }
     }
 
@@ -20,3 +90,27 @@ impl Ray {
         self.origin + self.direction * t
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_media_beyond_capacity() {
+        let absorption = |i: usize| Vec3::one() * (i + 1) as f64;
+        let depth = MAX_NESTED_MEDIA + 2;
+        let mut stack = MediumStack::new();
+        for i in 0..depth {
+            stack.push(absorption(i));
+            assert_eq!(stack.current(), absorption(i.min(MAX_NESTED_MEDIA - 1)));
+        }
+        for i in (0..depth).rev() {
+            assert_eq!(stack.current(), absorption(i.min(MAX_NESTED_MEDIA - 1)));
+            stack.pop();
+        }
+        assert!(stack.is_empty());
+        stack.pop();
+        assert!(stack.is_empty());
+        assert_eq!(stack.transmittance(1.0), Vec3::one());
+    }
+}
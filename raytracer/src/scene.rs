@@ -0,0 +1,196 @@
+use crate::atmosphere::Atmosphere;
+use crate::background::{Background, ConstantBackground};
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::light::Light;
+use crate::photon_map::PhotonMap;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use rand::Rng;
+
+/// Everything a path is traced against: the objects, the radiance of rays
+/// escaping them and an optional fog filling the space between them.
+///
+/// `lights` holds the indices of the emissive objects in `world`, collected
+/// when the scene is created, for sampling them directly. A light nested in a
+/// group is sampled through the top-level object holding it, which only picks
+/// its emissive members. Lights without any geometry are kept in
+/// `punctual_lights`. Lights given in total power are
+/// told the area of their surface at the same time.
+///
+/// `caustics` holds light that reached the surfaces through glass and mirrors,
+/// which `PathTracer` gathers instead of waiting for paths to find the lights
+/// through them.
+pub struct Scene {
+    pub world: HittableList,
+    pub lights: Vec<usize>,
+    pub punctual_lights: Vec<Box<dyn Light>>,
+    pub background: Box<dyn Background>,
+    pub atmosphere: Option<Atmosphere>,
+    pub caustics: Option<PhotonMap>,
+}
+
+impl Scene {
+    pub fn new(world: HittableList, background: Vec3) -> Self {
+        world.resolve_emitting_area();
+        let lights: Vec<usize> = (0..world.hittable_list.len())
+            .filter(|&i| world.hittable_list[i].is_emissive())
+            .collect();
+        Self {
+            world,
+            lights,
+            punctual_lights: Vec::new(),
+            background: Box::new(ConstantBackground::new(background)),
+            atmosphere: None,
+            caustics: None,
+        }
+    }
+
+    pub fn with_background(mut self, background: Box<dyn Background>) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_atmosphere(mut self, atmosphere: Atmosphere) -> Self {
+        self.atmosphere = Some(atmosphere);
+        self
+    }
+
+    pub fn with_light(mut self, light: Box<dyn Light>) -> Self {
+        self.punctual_lights.push(light);
+        self
+    }
+
+    /// Gathers caustics from `caustics`, traced with `PhotonMap::caustics` in
+    /// a scene with the same world.
+    pub fn with_caustics(mut self, caustics: PhotonMap) -> Self {
+        self.caustics = Some(caustics);
+        self
+    }
+
+    /// Direction from `origin` to a random point on a light picked uniformly.
+    pub fn sample_light(&self, origin: Vec3, time: f64) -> Option<Vec3> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let light = self.lights[rng.gen_range(0..self.lights.len())];
+        Some(self.world.hittable_list[light].random(origin, time))
+    }
+
+    /// Solid angle density of `sample_light` choosing direction `v`.
+    pub fn light_pdf(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        let sum: f64 = self
+            .lights
+            .iter()
+            .map(|&i| self.world.hittable_list[i].pdf_value(origin, v, time))
+            .sum();
+        sum / self.lights.len() as f64
+    }
+
+    /// Closest surface or fog scattering event along `r`. Rays travelling
+    /// inside an object are not affected by the atmosphere.
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let surface = self.world.hit(*r, t_min, t_max);
+        match &self.atmosphere {
+            Some(atmosphere) if r.media.is_empty() => {
+                let t_surface = surface.as_ref().map_or(t_max, |rec| rec.t);
+                atmosphere.hit(*r, t_min, t_surface).or(surface)
+            }
+            _ => surface,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XzRect;
+    use crate::hittable::{RotateY, Translate};
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_sample_transformed_light() {
+        let white = || Box::new(SolidColor::new(Vec3::one()));
+        let mut world = HittableList::new();
+        world.add(Box::new(Sphere::new(
+            Vec3::new(0.0, -1000.0, 0.0),
+            900.0,
+            Lambertian::new(white()),
+        )));
+        let light = XzRect::new(-0.5, 0.5, -0.5, 0.5, 0.0, DiffuseLight::new(white()));
+        let light = RotateY::new(30.0, Box::new(light));
+        world.add(Box::new(Translate::new(
+            Vec3::new(3.0, 10.0, -2.0),
+            Box::new(light),
+        )));
+        let scene = Scene::new(world, Vec3::zero());
+        assert_eq!(scene.lights, vec![1]);
+
+        // A unit square seen face on from a distance of 10.
+        let origin = Vec3::new(3.0, 0.0, -2.0);
+        let n = 2000;
+        let mut solid_angle = 0.0;
+        for _ in 0..n {
+            let v = scene.sample_light(origin, 0.0).unwrap();
+            let rec = scene.hit(&Ray::new(origin, v, 0.0), 0.001, f64::INFINITY);
+            assert!(rec.unwrap().mat_ptr.is_emissive());
+            solid_angle += 1.0 / scene.light_pdf(origin, v, 0.0) / n as f64;
+        }
+        let expected = 4.0 * f64::asin(1.0 / 401.0);
+        assert!((solid_angle - expected).abs() < 1e-3 * expected);
+    }
+
+    #[test]
+    fn test_sample_light_in_group() {
+        let white = || Box::new(SolidColor::new(Vec3::one()));
+        let mut lamp = HittableList::new();
+        let shade = Sphere::new(Vec3::new(0.0, 12.0, 0.0), 1.0, Lambertian::new(white()));
+        lamp.add(Box::new(shade));
+        let bulb = XzRect::new(-0.5, 0.5, -0.5, 0.5, 10.0, DiffuseLight::new(white()));
+        lamp.add(Box::new(bulb));
+        let mut world = HittableList::new();
+        world.add(Box::new(lamp));
+        let scene = Scene::new(world, Vec3::zero());
+        assert_eq!(scene.lights, vec![0]);
+
+        let origin = Vec3::zero();
+        let n = 2000;
+        let mut solid_angle = 0.0;
+        for _ in 0..n {
+            let v = scene.sample_light(origin, 0.0).unwrap();
+            let rec = scene.hit(&Ray::new(origin, v, 0.0), 0.001, f64::INFINITY);
+            assert!(rec.unwrap().mat_ptr.is_emissive());
+            solid_angle += 1.0 / scene.light_pdf(origin, v, 0.0) / n as f64;
+        }
+        let expected = 4.0 * f64::asin(1.0 / 401.0);
+        assert!((solid_angle - expected).abs() < 1e-3 * expected);
+    }
+
+    /// A light of 100 W on a rectangle of `area`, moved away from the origin
+    /// inside a group, and the radiance it emits.
+    fn powered_light_radiance(area: f64) -> f64 {
+        let white = Box::new(SolidColor::new(Vec3::one()));
+        let light = DiffuseLight::from_power(white, 100.0);
+        let light = XzRect::new(0.0, area, 0.0, 1.0, 1.0, light);
+        let mut group = HittableList::new();
+        group.add(Box::new(Translate::new(Vec3::one(), Box::new(light))));
+        let mut world = HittableList::new();
+        world.add(Box::new(group));
+        let scene = Scene::new(world, Vec3::zero());
+        let (rec, _) = scene.world.sample_surface(0.0).unwrap();
+        rec.mat_ptr.emitted(&rec).x()
+    }
+
+    #[test]
+    fn test_power_spreads_over_geometry() {
+        // Both sides radiate pi * L per unit area.
+        for area in [1.0, 6.0] {
+            let power = powered_light_radiance(area) * PI * area * 2.0;
+            assert!((power - 100.0).abs() < 1e-9);
+        }
+    }
+}
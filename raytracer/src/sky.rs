@@ -0,0 +1,365 @@
+//! Analytic daylight after Preetham, Shirley and Smits, "A Practical Analytic
+//! Model for Daylight" (1999).
+use crate::background::{Background, EnvironmentMap};
+use crate::onb::Onb;
+use crate::spectrum::{blackbody_rgb, luminance, xyz_to_rgb};
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Cosine of the sun's angular radius, 0.53 degrees across.
+const COS_SUN_RADIUS: f64 = 0.999989;
+/// Luminance of the sun above the atmosphere, in the same kcd/m^2 as the sky.
+const SUN_LUMINANCE: f64 = 2.0e6;
+/// Range of turbidities the model was fitted to.
+const MIN_TURBIDITY: f64 = 2.0;
+const MAX_TURBIDITY: f64 = 10.0;
+
+/// Perez et al. distribution `(1 + A e^(B / cos theta)) (1 + C e^(D gamma) +
+/// E cos^2 gamma)` of one of Y, x and y over the sky.
+struct Perez {
+    coefficients: [f64; 5],
+    scale: f64,
+}
+
+impl Perez {
+    fn new(coefficients: [f64; 5], zenith: f64, theta_sun: f64) -> Self {
+        let mut perez = Self {
+            coefficients,
+            scale: 1.0,
+        };
+        // Scaled so the distribution gives `zenith` straight up.
+        perez.scale = zenith / perez.f(1.0, theta_sun);
+        perez
+    }
+
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.coefficients;
+        (1.0 + a * f64::exp(b / cos_theta))
+            * (1.0 + c * f64::exp(d * gamma) + e * gamma.cos().powi(2))
+    }
+
+    fn value(&self, cos_theta: f64, gamma: f64) -> f64 {
+        self.scale * self.f(cos_theta, gamma)
+    }
+}
+
+/// Cubic in the sun's zenith angle, for the zenith chromaticity.
+fn zenith_polynomial(rows: [[f64; 4]; 3], turbidity: f64, theta_sun: f64) -> f64 {
+    let theta = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.0];
+    let t = [turbidity * turbidity, turbidity, 1.0];
+    (0..3)
+        .map(|i| t[i] * (0..4).map(|j| rows[i][j] * theta[j]).sum::<f64>())
+        .sum()
+}
+
+/// Fraction of sunlight at `lambda` micrometres that reaches the ground
+/// through Rayleigh and aerosol scattering.
+fn sun_transmittance(lambda: f64, turbidity: f64, theta_sun: f64) -> f64 {
+    let degrees = theta_sun.to_degrees();
+    let air_mass = 1.0 / (theta_sun.cos() + 0.15 * (93.885 - degrees).powf(-1.253));
+    let rayleigh = 0.008735 * lambda.powf(-4.08);
+    let beta = 0.04608 * turbidity - 0.04586;
+    let aerosol = beta * lambda.powf(-1.3);
+    f64::exp(-air_mass * (rayleigh + aerosol))
+}
+
+/// Unit vector towards the sun for an observer at `latitude` and `longitude`
+/// (degrees, north and east positive) on the given day at `utc_hours`. The
+/// result uses +y as up, +x as east and +z as south.
+pub fn sun_direction(latitude: f64, longitude: f64, month: u32, day: u32, utc_hours: f64) -> Vec3 {
+    const MONTH_START: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let n = (MONTH_START[(month.clamp(1, 12) - 1) as usize] + day) as f64;
+    let declination = (-23.44f64).to_radians() * f64::cos(2.0 * PI / 365.0 * (n + 10.0));
+    // Equation of time in minutes, the drift of solar noon over the year.
+    let b = 2.0 * PI * (n - 81.0) / 364.0;
+    let equation_of_time = 9.87 * (2.0 * b).sin() - 7.53 * b.cos() - 1.5 * b.sin();
+    let solar_hours = utc_hours + longitude / 15.0 + equation_of_time / 60.0;
+    let hour_angle = (15.0 * (solar_hours - 12.0)).to_radians();
+
+    let (sin_lat, cos_lat) = latitude.to_radians().sin_cos();
+    let (sin_dec, cos_dec) = declination.sin_cos();
+    let up = sin_lat * sin_dec + cos_lat * cos_dec * hour_angle.cos();
+    let east = -cos_dec * hour_angle.sin();
+    let north = cos_lat * sin_dec - sin_lat * cos_dec * hour_angle.cos();
+    Vec3::unit_vector(Vec3::new(east, up, -north))
+}
+
+/// Clear sky lit by the sun, with the sun disk itself and a diffuse ground
+/// below the horizon. `turbidity` runs from 2 (very clear) to 10 (hazy), and
+/// values outside that range are clamped to it. Radiance is roughly in
+/// kcd/m^2, so a camera usually needs `with_intensity` to expose it.
+///
+/// The sky is importance sampled through a tabulated `EnvironmentMap` and the
+/// sun by its cone of directions, choosing between them by their power.
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    sun_radiance: Vec3,
+    luminance: Perez,
+    x: Perez,
+    y: Perez,
+    ground: Vec3,
+    intensity: f64,
+    sky_map: EnvironmentMap,
+    sun_probability: f64,
+}
+
+impl PreethamSky {
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Vec3) -> Self {
+        let sun_direction = Vec3::unit_vector(sun_direction);
+        let t = turbidity.clamp(MIN_TURBIDITY, MAX_TURBIDITY);
+        // The model only covers the sun above the horizon.
+        let theta_sun = sun_direction.y().clamp(0.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let zenith_x = zenith_polynomial(
+            [
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+            t,
+            theta_sun,
+        );
+        let zenith_y = zenith_polynomial(
+            [
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+            t,
+            theta_sun,
+        );
+        let luminance_coefficients = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let x_coefficients = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let y_coefficients = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let sun_radiance = if sun_direction.y() > 0.0 {
+            let transmittance = Vec3::new(
+                sun_transmittance(0.61, t, theta_sun),
+                sun_transmittance(0.55, t, theta_sun),
+                sun_transmittance(0.465, t, theta_sun),
+            );
+            SUN_LUMINANCE * blackbody_rgb(5778.0) * transmittance
+        } else {
+            Vec3::zero()
+        };
+
+        let mut sky = Self {
+            sun_direction,
+            sun_radiance,
+            luminance: Perez::new(luminance_coefficients, zenith_luminance, theta_sun),
+            x: Perez::new(x_coefficients, zenith_x, theta_sun),
+            y: Perez::new(y_coefficients, zenith_y, theta_sun),
+            ground: Vec3::zero(),
+            intensity: 1.0,
+            sky_map: EnvironmentMap::from_pixels(1, 1, vec![Vec3::zero()]).unwrap(),
+            sun_probability: 0.0,
+        };
+
+        // The ground reflects the sky and the sun diffusely.
+        let sun_solid_angle = 2.0 * PI * (1.0 - COS_SUN_RADIUS);
+        let sun_irradiance = sun_radiance * sun_solid_angle * sun_direction.y().max(0.0);
+        let sky_irradiance = sky.sky_irradiance();
+        sky.ground = ground_albedo * (sky_irradiance + sun_irradiance) / PI;
+
+        sky.sky_map = EnvironmentMap::from_fn(256, 128, |d| sky.sky_radiance(d)).unwrap();
+        let sky_power = sky.sky_power();
+        let sun_power = luminance(sun_radiance) * sun_solid_angle;
+        sky.sun_probability = if sun_power > 0.0 {
+            sun_power / (sun_power + sky_power)
+        } else {
+            0.0
+        };
+        sky
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    /// Sky or ground radiance in `direction`, without the sun disk.
+    fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        let d = Vec3::unit_vector(direction);
+        if d.y() < 0.0 {
+            return self.ground;
+        }
+        let cos_theta = d.y().max(1e-3);
+        let gamma = Vec3::dot(d, self.sun_direction).clamp(-1.0, 1.0).acos();
+        let big_y = self.luminance.value(cos_theta, gamma);
+        let x = self.x.value(cos_theta, gamma);
+        let y = self.y.value(cos_theta, gamma);
+        if y <= 0.0 {
+            return Vec3::zero();
+        }
+        let xyz = Vec3::new(x / y * big_y, big_y, (1.0 - x - y) / y * big_y);
+        let rgb = xyz_to_rgb(xyz);
+        Vec3::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    /// Midpoint rule over the upper hemisphere of `radiance * cos(theta)`.
+    fn sky_irradiance(&self) -> Vec3 {
+        let (n_theta, n_phi) = (64, 128);
+        let mut irradiance = Vec3::zero();
+        for i in 0..n_theta {
+            let theta = 0.5 * PI * (i as f64 + 0.5) / n_theta as f64;
+            let weight =
+                theta.cos() * theta.sin() * (0.5 * PI / n_theta as f64) * (2.0 * PI / n_phi as f64);
+            for j in 0..n_phi {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n_phi as f64;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                irradiance += self.sky_radiance(d) * weight;
+            }
+        }
+        irradiance
+    }
+
+    /// Luminance integrated over all directions, excluding the sun.
+    fn sky_power(&self) -> f64 {
+        let (n_theta, n_phi) = (128, 256);
+        let mut power = 0.0;
+        for i in 0..n_theta {
+            let theta = PI * (i as f64 + 0.5) / n_theta as f64;
+            let weight = theta.sin() * (PI / n_theta as f64) * (2.0 * PI / n_phi as f64);
+            for j in 0..n_phi {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n_phi as f64;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                power += luminance(self.sky_radiance(d)) * weight;
+            }
+        }
+        power
+    }
+
+    fn in_sun(&self, direction: Vec3) -> bool {
+        Vec3::dot(Vec3::unit_vector(direction), self.sun_direction) >= COS_SUN_RADIUS
+    }
+}
+
+impl Background for PreethamSky {
+    fn value(&self, direction: Vec3) -> Vec3 {
+        let mut radiance = self.sky_radiance(direction);
+        if self.in_sun(direction) {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+
+    fn sample(&self) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        if rng.gen::<f64>() >= self.sun_probability {
+            return self.sky_map.sample();
+        }
+        let z = 1.0 + rng.gen::<f64>() * (COS_SUN_RADIUS - 1.0);
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let sin_theta = (1.0 - z * z).sqrt();
+        Onb::build_from_w(self.sun_direction).local(Vec3::new(
+            phi.cos() * sin_theta,
+            phi.sin() * sin_theta,
+            z,
+        ))
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+        let sun_pdf = if self.in_sun(direction) {
+            1.0 / (2.0 * PI * (1.0 - COS_SUN_RADIUS))
+        } else {
+            0.0
+        };
+        self.sun_probability * sun_pdf + (1.0 - self.sun_probability) * self.sky_map.pdf(direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sun_direction() {
+        // Equinox noon on the equator puts the sun overhead, within the
+        // equation of time.
+        let noon = sun_direction(0.0, 0.0, 3, 20, 12.0);
+        assert!(noon.y() > 0.99);
+        // Mid-latitude summer morning: the sun is up, in the east.
+        let morning = sun_direction(48.0, 0.0, 6, 21, 8.0);
+        assert!(morning.y() > 0.0 && morning.x() > 0.0);
+        assert!(sun_direction(48.0, 0.0, 6, 21, 0.0).y() < 0.0);
+    }
+
+    #[test]
+    fn test_sky_sampling() {
+        let sky = PreethamSky::new(Vec3::new(1.0, 1.0, 0.3), 3.0, Vec3::one() * 0.3);
+        assert!(sky.sun_probability > 0.0 && sky.sun_probability < 1.0);
+        let sun_solid_angle = 2.0 * PI * (1.0 - COS_SUN_RADIUS);
+        let expected = sky.sky_power() + luminance(sky.sun_radiance) * sun_solid_angle;
+
+        // Luminance over the sphere estimated with the sky's own sampling.
+        let n = 50000;
+        let estimate: f64 = (0..n)
+            .map(|_| {
+                let d = sky.sample();
+                luminance(sky.value(d)) / sky.pdf(d)
+            })
+            .sum::<f64>()
+            / n as f64;
+        assert!((estimate / expected - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_turbidity_extremes() {
+        let sun = Vec3::new(0.3, 0.2, 1.0);
+        let sky = |turbidity: f64| PreethamSky::new(sun, turbidity, Vec3::one() * 0.3);
+        let directions = [
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.05, 0.0),
+            Vec3::new(-0.3, 0.4, -1.0),
+            sun,
+        ];
+        for (turbidity, clamped) in [(0.0, 2.0), (1.0, 2.0), (10.0, 10.0), (50.0, 10.0)] {
+            let (sky, clamped) = (sky(turbidity), sky(clamped));
+            for d in directions {
+                let radiance = sky.value(Vec3::unit_vector(d));
+                assert!(radiance.x() >= 0.0 && radiance.y() >= 0.0 && radiance.z() >= 0.0);
+                assert!(luminance(radiance).is_finite());
+                assert_eq!(radiance, clamped.value(Vec3::unit_vector(d)));
+            }
+        }
+        // Haze dims the sun.
+        assert!(luminance(sky(2.0).sun_radiance) > luminance(sky(10.0).sun_radiance));
+    }
+}
@@ -0,0 +1,330 @@
+//! Wavelength helpers for dispersive materials and the spectral render mode.
+//! Wavelengths are in nm.
+use crate::vec3::Vec3;
+use std::ops::{Add, Div, Mul};
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+/// Sodium D line, where catalogue IORs are usually quoted.
+pub const LAMBDA_D: f64 = 587.6;
+
+/// Wavelengths carried by each path in spectral mode.
+pub const N_SPECTRAL_SAMPLES: usize = 4;
+
+/// Integral of each `xyz_to_rgb(cie_xyz(lambda))` channel over the visible range,
+/// used to make a uniformly sampled wavelength average to white.
+const WHITE_RGB: [f64; 3] = [128.361023, 101.538081, 97.064801];
+
+pub fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn gaussian(x: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let sigma = if x < mu { sigma1 } else { sigma2 };
+    let t = (x - mu) / sigma;
+    f64::exp(-0.5 * t * t)
+}
+
+/// CIE 1931 colour matching functions, multi-lobe fit of Wyman et al. 2013.
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let x = 1.056 * gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2);
+    let y =
+        0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1);
+    let z =
+        1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8);
+    Vec3::new(x, y, z)
+}
+
+/// CIE XYZ to linear sRGB.
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+/// Relative luminance of a linear sRGB colour.
+pub fn luminance(rgb: Vec3) -> f64 {
+    0.2126 * rgb.x() + 0.7152 * rgb.y() + 0.0722 * rgb.z()
+}
+
+/// Planck's law, spectral radiance of a blackbody in W / (sr m^2 nm).
+pub fn blackbody(lambda: f64, kelvin: f64) -> f64 {
+    const C: f64 = 299792458.0;
+    const H: f64 = 6.62606957e-34;
+    const KB: f64 = 1.3806488e-23;
+    let l = lambda * 1e-9;
+    let radiance = 2.0 * H * C * C / (l.powi(5) * (f64::exp(H * C / (l * KB * kelvin)) - 1.0));
+    radiance * 1e-9
+}
+
+/// Linear sRGB colour of a blackbody at `kelvin`, normalized to unit luminance,
+/// or black for temperatures too low to give off any visible light.
+pub fn blackbody_rgb(kelvin: f64) -> Vec3 {
+    let steps = 80;
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let mut rgb = Vec3::zero();
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * step;
+        rgb += wavelength_to_rgb_weight(lambda) * blackbody(lambda, kelvin);
+    }
+    let rgb = Vec3::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0));
+    let y = luminance(rgb);
+    if y <= 0.0 || !y.is_finite() {
+        return Vec3::zero();
+    }
+    rgb / y
+}
+
+pub fn sample_wavelength(u: f64) -> f64 {
+    LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+/// RGB weight of a single wavelength picked by `sample_wavelength`, so that
+/// the expected value over all wavelengths is `(1, 1, 1)`.
+pub fn wavelength_to_rgb_weight(lambda: f64) -> Vec3 {
+    let rgb = xyz_to_rgb(cie_xyz(lambda)) * (LAMBDA_MAX - LAMBDA_MIN);
+    Vec3::new(
+        rgb.x() / WHITE_RGB[0],
+        rgb.y() / WHITE_RGB[1],
+        rgb.z() / WHITE_RGB[2],
+    )
+}
+
+/// Index of refraction as a function of wavelength.
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub enum RefractiveIndex {
+    Constant(f64),
+    /// `n = a + b / lambda^2`, lambda in micrometres.
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// `n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i)`, lambda in micrometres.
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl RefractiveIndex {
+    /// Schott N-BK7 crown glass.
+    pub fn bk7() -> Self {
+        RefractiveIndex::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    pub fn diamond() -> Self {
+        RefractiveIndex::Sellmeier {
+            b: [4.3356, 0.3306, 0.0],
+            c: [0.011236, 0.030625, 0.0],
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, RefractiveIndex::Constant(_))
+    }
+
+    pub fn at(&self, lambda: f64) -> f64 {
+        let l = lambda / 1000.0;
+        let l2 = l * l;
+        match self {
+            RefractiveIndex::Constant(n) => *n,
+            RefractiveIndex::Cauchy { a, b } => a + b / l2,
+            RefractiveIndex::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for (bi, ci) in b.iter().zip(c.iter()) {
+                    n2 += bi * l2 / (l2 - ci);
+                }
+                n2.sqrt()
+            }
+        }
+    }
+}
+
+/// Hero wavelength plus equally spaced companions over the visible range.
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub struct SampledWavelengths {
+    pub lambda: [f64; N_SPECTRAL_SAMPLES],
+    pub pdf: [f64; N_SPECTRAL_SAMPLES],
+}
+
+impl SampledWavelengths {
+    pub fn sample_uniform(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = sample_wavelength(u);
+        let delta = range / N_SPECTRAL_SAMPLES as f64;
+        let mut lambda = [hero; N_SPECTRAL_SAMPLES];
+        for (i, l) in lambda.iter_mut().enumerate().skip(1) {
+            *l = hero + delta * i as f64;
+            if *l > LAMBDA_MAX {
+                *l -= range;
+            }
+        }
+        Self {
+            lambda,
+            pdf: [1.0 / range; N_SPECTRAL_SAMPLES],
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Drops the companion wavelengths once the path depends on the hero alone,
+    /// e.g. after refraction through a dispersive surface.
+    pub fn terminate_secondary(&mut self) {
+        if self.pdf[1] == 0.0 {
+            return;
+        }
+        for pdf in self.pdf.iter_mut().skip(1) {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= N_SPECTRAL_SAMPLES as f64;
+    }
+}
+
+/// Radiance or reflectance evaluated at a set of `SampledWavelengths`.
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub struct SampledSpectrum {
+    pub values: [f64; N_SPECTRAL_SAMPLES],
+}
+
+impl SampledSpectrum {
+    pub fn zero() -> Self {
+        Self {
+            values: [0.0; N_SPECTRAL_SAMPLES],
+        }
+    }
+
+    /// Upsamples a linear RGB triple with a smooth red/green/blue basis that
+    /// sums to one, so white stays flat and reflectances stay within `[0, 1]`.
+    pub fn from_rgb(rgb: Vec3, wavelengths: &SampledWavelengths) -> Self {
+        let mut values = [0.0; N_SPECTRAL_SAMPLES];
+        for (v, l) in values.iter_mut().zip(wavelengths.lambda.iter()) {
+            let b = 1.0 - smoothstep(470.0, 515.0, *l);
+            let r = smoothstep(565.0, 610.0, *l);
+            let g = 1.0 - b - r;
+            *v = rgb.x() * r + rgb.y() * g + rgb.z() * b;
+        }
+        Self { values }
+    }
+
+    /// Film response of the sampled radiance as linear sRGB, white balanced so a
+    /// flat spectrum of one maps to `(1, 1, 1)`.
+    pub fn to_rgb(self, wavelengths: &SampledWavelengths) -> Vec3 {
+        let mut xyz = Vec3::zero();
+        let samples = wavelengths.lambda.iter().zip(wavelengths.pdf.iter());
+        for ((lambda, pdf), value) in samples.zip(self.values.iter()) {
+            if *pdf != 0.0 {
+                xyz += cie_xyz(*lambda) * (value / pdf);
+            }
+        }
+        let rgb = xyz_to_rgb(xyz / N_SPECTRAL_SAMPLES as f64);
+        Vec3::new(
+            rgb.x() / WHITE_RGB[0],
+            rgb.y() / WHITE_RGB[1],
+            rgb.z() / WHITE_RGB[2],
+        )
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values.iter()) {
+            *v += o;
+        }
+        Self { values }
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(rhs.values.iter()) {
+            *v *= o;
+        }
+        Self { values }
+    }
+}
+
+impl Div<f64> for SampledSpectrum {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self {
+        let mut values = self.values;
+        for v in values.iter_mut() {
+            *v /= rhs;
+        }
+        Self { values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_white_round_trip() {
+        let mut sum = Vec3::zero();
+        let n = 1000;
+        for i in 0..n {
+            let wavelengths = SampledWavelengths::sample_uniform((i as f64 + 0.5) / n as f64);
+            let white = SampledSpectrum::from_rgb(Vec3::one(), &wavelengths);
+            sum += white.to_rgb(&wavelengths) / n as f64;
+        }
+        assert!((sum.x() - 1.0).abs() < 1e-2);
+        assert!((sum.y() - 1.0).abs() < 1e-2);
+        assert!((sum.z() - 1.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_terminate_secondary_keeps_hero_weight() {
+        let mut wavelengths = SampledWavelengths::sample_uniform(0.3);
+        let before = wavelengths.pdf[0];
+        wavelengths.terminate_secondary();
+        wavelengths.terminate_secondary();
+        assert_eq!(wavelengths.pdf[0], before / N_SPECTRAL_SAMPLES as f64);
+        assert_eq!(wavelengths.pdf[3], 0.0);
+    }
+
+    #[test]
+    fn test_blackbody_color_temperature() {
+        let candle = blackbody_rgb(1900.0);
+        // The film is balanced to an equal energy white, close to 5500K.
+        let noon = blackbody_rgb(5500.0);
+        let sky = blackbody_rgb(10000.0);
+        assert!(candle.x() > candle.z());
+        assert!(sky.z() > sky.x());
+        assert!((luminance(noon) - 1.0).abs() < 1e-9);
+        assert!((noon.x() / noon.z() - 1.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_cold_blackbody_is_black() {
+        for kelvin in [0.0, 1.0, 10.0, 30.0, 50.0, 100.0] {
+            let rgb = blackbody_rgb(kelvin);
+            assert!(rgb.x().is_finite() && rgb.y().is_finite() && rgb.z().is_finite());
+        }
+        assert_eq!(blackbody_rgb(10.0), Vec3::zero());
+        assert!((luminance(blackbody_rgb(500.0)) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bk7_normal_dispersion() {
+        let bk7 = RefractiveIndex::bk7();
+        assert!((bk7.at(LAMBDA_D) - 1.5168).abs() < 1e-3);
+        assert!(bk7.at(450.0) > bk7.at(650.0));
+    }
+}
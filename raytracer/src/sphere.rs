@@ -1,8 +1,39 @@
 use crate::aabb::Aabb;
+use crate::aarect::area_pdf;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Direction from `origin` to a random point of the sphere. From outside only
+/// the cone the sphere subtends is sampled, from inside the whole surface.
+pub fn sample_sphere(center: Vec3, radius: f64, origin: Vec3) -> Vec3 {
+    let direction = center - origin;
+    let distance_squared = direction.squared_length();
+    if distance_squared <= radius * radius {
+        return center + radius * Vec3::random_unit_vector() - origin;
+    }
+    let mut rng = rand::thread_rng();
+    let cos_theta_max = (1.0 - radius * radius / distance_squared).sqrt();
+    let z = 1.0 + rng.gen::<f64>() * (cos_theta_max - 1.0);
+    let phi = 2.0 * PI * rng.gen::<f64>();
+    let sin_theta = (1.0 - z * z).sqrt();
+    let uvw = Onb::build_from_w(direction);
+    uvw.local(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+}
+
+/// Solid angle density of `sample_sphere` for a direction `v` hitting at `rec`.
+pub fn sphere_pdf(center: Vec3, radius: f64, origin: Vec3, v: Vec3, rec: &HitRecord) -> f64 {
+    let distance_squared = (center - origin).squared_length();
+    if distance_squared <= radius * radius {
+        return area_pdf(rec, v, 4.0 * PI * radius * radius);
+    }
+    let cos_theta_max = (1.0 - radius * radius / distance_squared).sqrt();
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
 
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub struct Sphere<Material> {
@@ -30,7 +61,7 @@ impl<M: Material> Sphere<M> {
 }
 
 impl<M: Material> Hittable for Sphere<M> {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = r.origin - self.center;
         let a = r.direction.squared_length();
         let half_b = Vec3::dot(oc, r.direction);
@@ -64,4 +95,31 @@ impl<M: Material> Hittable for Sphere<M> {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3, time: f64) -> f64 {
+        match self.hit(Ray::new(origin, v, time), 0.001, f64::INFINITY) {
+            Some(rec) => sphere_pdf(self.center, self.radius, origin, v, &rec),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, _time: f64) -> Vec3 {
+        sample_sphere(self.center, self.radius, origin)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+
+    fn sample_surface(&self, time: f64) -> Option<(HitRecord<'_>, f64)> {
+        let outward_normal = Vec3::random_unit_vector();
+        let p = self.center + self.radius * outward_normal;
+        let (u, v) = Sphere::<M>::get_sphere_uv(&outward_normal);
+        let rec = HitRecord::on_surface(p, u, v, outward_normal, time, &self.mat_ptr);
+        Some((rec, 1.0 / (4.0 * PI * self.radius * self.radius)))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(4.0 * PI * self.radius * self.radius)
+    }
 }
@@ -0,0 +1,167 @@
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Dielectric, Isotropic, Material, ScatterRecord};
+use crate::ray::Ray;
+use crate::texture::SolidColor;
+use crate::vec3::Vec3;
+use rand::Rng;
+
+/// Random walk subsurface scattering inside a closed `boundary` (a `Sphere`,
+/// a `Box_`, a mesh, ...). Light refracts through a smooth interface and then
+/// takes exponentially distributed free flights between isotropic scattering
+/// events, like `ConstantMedium`, until it leaves the boundary again.
+///
+/// The boundary has to report `front_face` consistently, rays hitting a back
+/// face are taken to be inside.
+pub struct Subsurface {
+    boundary: Box<dyn Hittable>,
+    majorant: f64,
+    /// Probability of a tentative collision being taken as a scattering event
+    /// rather than a null collision.
+    real_probability: f64,
+    interface: Dielectric,
+    phase_function: Isotropic,
+    null_collision: NullCollision,
+}
+
+impl Subsurface {
+    pub fn new(boundary: Box<dyn Hittable>, mean_free_path: f64, albedo: Vec3, ior: f64) -> Self {
+        Self {
+            boundary,
+            majorant: 1.0 / mean_free_path,
+            real_probability: 1.0,
+            interface: Dielectric::new(ior),
+            phase_function: Isotropic::new(Box::new(SolidColor::new(albedo))),
+            null_collision: NullCollision {
+                attenuation: Vec3::one(),
+            },
+        }
+    }
+
+    /// Builds the medium from scattering and absorption coefficients, which
+    /// may differ per channel.
+    ///
+    /// Free flights are sampled by delta tracking against the largest
+    /// extinction. Tentative collisions become scattering events with the
+    /// probability given by the extinction averaged over the channels, and
+    /// both outcomes are weighted per channel so that every channel sees its
+    /// own transmittance. Null collisions carry on in a straight line but
+    /// take up a bounce of the path, so strongly coloured media need a larger
+    /// maximum depth.
+    pub fn from_coefficients(
+        boundary: Box<dyn Hittable>,
+        sigma_s: Vec3,
+        sigma_a: Vec3,
+        ior: f64,
+    ) -> Self {
+        let sigma_t = sigma_s + sigma_a;
+        let majorant = sigma_t.x().max(sigma_t.y()).max(sigma_t.z());
+        let mean_sigma_t = (sigma_t.x() + sigma_t.y() + sigma_t.z()) / 3.0;
+        let null_attenuation = if majorant > mean_sigma_t {
+            (Vec3::one() * majorant - sigma_t) / (majorant - mean_sigma_t)
+        } else {
+            Vec3::one()
+        };
+        Self {
+            real_probability: mean_sigma_t / majorant,
+            null_collision: NullCollision {
+                attenuation: null_attenuation,
+            },
+            ..Self::new(boundary, 1.0 / majorant, sigma_s / mean_sigma_t, ior)
+        }
+    }
+}
+
+/// Tentative collision in a medium that the ray passes straight through,
+/// reweighting the channels by how likely each was to pass.
+struct NullCollision {
+    attenuation: Vec3,
+}
+
+impl Material for NullCollision {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        let scattered = Ray::new(hit_record.p, r_in.direction, r_in.time);
+        Some(ScatterRecord::new(scattered, self.attenuation, true))
+    }
+
+    fn is_volumetric(&self) -> bool {
+        true
+    }
+}
+
+impl Hittable for Subsurface {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rec = self.boundary.hit(r, t_min, f64::INFINITY)?;
+        if !rec.front_face {
+            let ray_length = r.direction.length();
+            let distance_inside_boundary = (rec.t - t_min) * ray_length;
+            let mut rng = rand::thread_rng();
+            let hit_distance = -f64::ln(rng.gen::<f64>()) / self.majorant;
+            if hit_distance < distance_inside_boundary {
+                let t = t_min + hit_distance / ray_length;
+                if t > t_max {
+                    return None;
+                }
+                let material: &dyn Material = if rng.gen::<f64>() < self.real_probability {
+                    &self.phase_function
+                } else {
+                    &self.null_collision
+                };
+                let normal = Vec3::new(1.0, 0.0, 0.0);
+                let hit_record = HitRecord::new(r.at(t), t, rec.u, rec.v, normal, r, material);
+                return Some(hit_record);
+            }
+        }
+        if rec.t > t_max {
+            return None;
+        }
+        rec.mat_ptr = &self.interface;
+        Some(rec)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn test_channels_see_their_own_transmittance() {
+        let material = Lambertian::new(Box::new(SolidColor::new(Vec3::one())));
+        let ball = Box::new(Sphere::new(Vec3::zero(), 1.0, material));
+        let sigma_a = Vec3::new(0.5, 1.0, 2.0);
+        let medium = Subsurface::from_coefficients(ball, Vec3::zero(), sigma_a, 1.5);
+
+        // Walk from the centre to the boundary: null collisions reweight the
+        // path, scattering events end it since nothing is scattered.
+        let n = 100000;
+        let mut total = Vec3::zero();
+        for _ in 0..n {
+            let mut r = Ray::new(Vec3::zero(), Vec3::new(1.0, 0.0, 0.0), 0.0);
+            let mut weight = Vec3::one();
+            loop {
+                // Collisions closer to the boundary than `t_min` miss it
+                let hit = medium.hit(r, 0.0001, f64::INFINITY);
+                let Some(rec) = hit.filter(|rec| rec.mat_ptr.is_volumetric()) else {
+                    total += weight;
+                    break;
+                };
+                let srec = rec.mat_ptr.scatter(&r, &rec).unwrap();
+                weight = weight * srec.attenuation;
+                if weight == Vec3::zero() {
+                    break;
+                }
+                r = srec.scattered;
+            }
+        }
+        let mean = total / n as f64;
+        assert!((mean.x() - f64::exp(-0.5)).abs() < 0.02);
+        assert!((mean.y() - f64::exp(-1.0)).abs() < 0.02);
+        assert!((mean.z() - f64::exp(-2.0)).abs() < 0.02);
+    }
+}
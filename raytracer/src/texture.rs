@@ -1,4 +1,5 @@
 use crate::perlin::Perlin;
+use crate::spectrum;
 use crate::vec3::Vec3;
 use image::{DynamicImage, GenericImage, GenericImageView, RgbImage};
 use std::path::Path;
@@ -13,8 +14,13 @@ fn clamp(x: f64) -> f64 {
     }
 }
 
-pub trait Texture {
+pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3;
+
+    /// Opacity in `[0, 1]`, used by `AlphaCutout`.
+    fn alpha(&self, _u: f64, _v: f64, _p: &Vec3) -> f64 {
+        1.0
+    }
 }
 
 pub struct SolidColor {
@@ -78,6 +84,58 @@ impl Texture for NoiseTexture {
     }
 }
 
+/// Perlin turbulence, e.g. as the density field of a cloud.
+pub struct TurbulenceTexture {
+    noise: Perlin,
+    scale: f64,
+    depth: i32,
+}
+
+impl TurbulenceTexture {
+    pub fn new(sc: f64, depth: i32) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale: sc,
+            depth,
+        }
+    }
+}
+
+impl Texture for TurbulenceTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        self.noise.turb(&(*p * self.scale), self.depth) * Vec3::one()
+    }
+}
+
+/// Blackbody glow driven by a temperature field, e.g. a fire simulation. The
+/// first channel of `temperature` times `kelvin_scale` is the temperature, and
+/// the radiance grows with its fourth power, reaching `intensity` at 1000K.
+pub struct BlackbodyTexture {
+    temperature: Box<dyn Texture>,
+    kelvin_scale: f64,
+    intensity: f64,
+}
+
+impl BlackbodyTexture {
+    pub fn new(temperature: Box<dyn Texture>, kelvin_scale: f64, intensity: f64) -> Self {
+        Self {
+            temperature,
+            kelvin_scale,
+            intensity,
+        }
+    }
+}
+
+impl Texture for BlackbodyTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let kelvin = self.temperature.value(u, v, p).x() * self.kelvin_scale;
+        if kelvin <= 0.0 || !kelvin.is_finite() {
+            return Vec3::zero();
+        }
+        self.intensity * (kelvin / 1000.0).powi(4) * spectrum::blackbody_rgb(kelvin)
+    }
+}
+
 pub struct ImageTexture {
     width: u32,
     height: u32,
@@ -90,10 +148,8 @@ impl ImageTexture {
         let (width, height) = img.dimensions();
         Self { width, height, img }
     }
-}
 
-impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+    fn texel(&self, u: f64, v: f64) -> image::Rgba<u8> {
         let u = clamp(u);
         let v = clamp(v);
         let mut i = (u * self.width as f64) as u32;
@@ -105,11 +161,21 @@ impl Texture for ImageTexture {
             let j = self.height - 1;
         }
         let j = self.height - j - 1;
-        let data = self.img.get_pixel(i, j);
+        self.img.get_pixel(i, j)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let data = self.texel(u, v);
         Vec3::new(
             1.0 / 255.0 * data[0] as f64,
             1.0 / 255.0 * data[1] as f64,
             1.0 / 255.0 * data[2] as f64,
         )
     }
+
+    fn alpha(&self, u: f64, v: f64, _p: &Vec3) -> f64 {
+        1.0 / 255.0 * self.texel(u, v)[3] as f64
+    }
 }
@@ -0,0 +1,208 @@
+use crate::aabb::Aabb;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Dense voxel grid of densities, e.g. a smoke or fire simulation export,
+/// stretched over `bounds`. As a `Texture` it returns the trilinearly
+/// interpolated density at `p` (zero outside the bounds), so it can drive a
+/// `HeterogeneousMedium` directly.
+pub struct VolumeGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<f32>,
+    bounds: Aabb,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> Result<i32> {
+    let b = bytes
+        .get(offset..offset + 4)
+        .ok_or_else(|| invalid("truncated volume header"))?;
+    Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Product of `dims`, failing if it does not fit in memory.
+fn voxel_count(dims: &[usize]) -> Result<usize> {
+    dims.iter()
+        .try_fold(1usize, |n, &d| n.checked_mul(d))
+        .ok_or_else(|| invalid("volume too large"))
+}
+
+fn read_f32s(bytes: &[u8], offset: usize, count: usize) -> Result<Vec<f32>> {
+    let end = count
+        .checked_mul(4)
+        .and_then(|n| n.checked_add(offset))
+        .ok_or_else(|| invalid("volume too large"))?;
+    let b = bytes
+        .get(offset..end)
+        .ok_or_else(|| invalid("truncated volume data"))?;
+    Ok(b.chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+impl VolumeGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<f32>, bounds: Aabb) -> Result<Self> {
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(invalid("empty volume"));
+        }
+        if data.len() != voxel_count(&[nx, ny, nz])? {
+            return Err(invalid("voxel count does not match"));
+        }
+        Ok(Self {
+            nx,
+            ny,
+            nz,
+            data,
+            bounds,
+        })
+    }
+
+    /// Reads a Mitsuba style `.vol` grid: the bytes `VOL`, version 3, the
+    /// encoding (1 for float32), the resolution, the channel count and the
+    /// bounding box, followed by little endian floats with x varying fastest.
+    /// Only the first channel is kept.
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 4 || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+            return Err(invalid("not a version 3 .vol file"));
+        }
+        if read_i32(bytes, 4)? != 1 {
+            return Err(invalid("only float32 volumes are supported"));
+        }
+        let dims = [
+            read_i32(bytes, 8)?,
+            read_i32(bytes, 12)?,
+            read_i32(bytes, 16)?,
+        ];
+        let channels = read_i32(bytes, 20)?;
+        if dims.iter().any(|d| *d <= 0) || channels <= 0 {
+            return Err(invalid("empty volume"));
+        }
+        let (nx, ny, nz) = (dims[0] as usize, dims[1] as usize, dims[2] as usize);
+        let channels = channels as usize;
+        let b = read_f32s(bytes, 24, 6)?;
+        let bounds = Aabb::new(
+            Vec3::new(b[0] as f64, b[1] as f64, b[2] as f64),
+            Vec3::new(b[3] as f64, b[4] as f64, b[5] as f64),
+        );
+        let values = read_f32s(bytes, 48, voxel_count(&[nx, ny, nz, channels])?)?;
+        let data = values.into_iter().step_by(channels).collect();
+        Self::new(nx, ny, nz, data, bounds)
+    }
+
+    /// Reads a headerless grid of little endian float32 values.
+    pub fn load_raw(path: &Path, nx: usize, ny: usize, nz: usize, bounds: Aabb) -> Result<Self> {
+        let data = read_f32s(&fs::read(path)?, 0, voxel_count(&[nx, ny, nz])?)?;
+        Self::new(nx, ny, nz, data, bounds)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb::new(self.bounds.minimum, self.bounds.maximum)
+    }
+
+    /// Largest voxel value, a valid majorant for delta tracking.
+    pub fn max_density(&self) -> f64 {
+        self.data.iter().fold(0.0f32, |m, d| m.max(*d)) as f64
+    }
+
+    fn voxel(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.data[(k * self.ny + j) * self.nx + i] as f64
+    }
+
+    pub fn density(&self, p: &Vec3) -> f64 {
+        let min = self.bounds.minimum;
+        let extent = self.bounds.maximum - min;
+        let local = [
+            (p.x() - min.x()) / extent.x(),
+            (p.y() - min.y()) / extent.y(),
+            (p.z() - min.z()) / extent.z(),
+        ];
+        if local.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return 0.0;
+        }
+
+        // Voxel values sit at cell centres.
+        let n = [self.nx, self.ny, self.nz];
+        let mut base = [0; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3 {
+            let x = (local[axis] * n[axis] as f64 - 0.5).clamp(0.0, (n[axis] - 1) as f64);
+            base[axis] = (x.floor() as usize).min(n[axis].saturating_sub(2));
+            frac[axis] = (x - base[axis] as f64).min(1.0);
+        }
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let i = (base[0] + di).min(self.nx - 1);
+                    let j = (base[1] + dj).min(self.ny - 1);
+                    let k = (base[2] + dk).min(self.nz - 1);
+                    let w = (if di == 1 { frac[0] } else { 1.0 - frac[0] })
+                        * (if dj == 1 { frac[1] } else { 1.0 - frac[1] })
+                        * (if dk == 1 { frac[2] } else { 1.0 - frac[2] });
+                    accum += w * self.voxel(i, j, k);
+                }
+            }
+        }
+        accum
+    }
+}
+
+impl Texture for VolumeGrid {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        self.density(p) * Vec3::one()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vol_bytes(dims: [i32; 3], channels: i32, bounds: [f32; 6], data: &[f32]) -> Vec<u8> {
+        let mut bytes = b"VOL".to_vec();
+        bytes.push(3);
+        for v in [1, dims[0], dims[1], dims[2], channels] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in bounds.iter().chain(data.iter()) {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_load_vol() {
+        let data = [0.0, 9.0, 1.0, 9.0];
+        let bytes = vol_bytes([2, 1, 1], 2, [0.0, 0.0, 0.0, 2.0, 1.0, 1.0], &data);
+        let grid = VolumeGrid::from_bytes(&bytes).unwrap();
+        assert_eq!(grid.max_density(), 1.0);
+        assert_eq!(grid.bounds().maximum, Vec3::new(2.0, 1.0, 1.0));
+        assert!(VolumeGrid::from_bytes(&bytes[..40]).is_err());
+
+        let huge = vol_bytes([i32::MAX, i32::MAX, i32::MAX], 2, [0.0; 6], &data);
+        assert!(VolumeGrid::from_bytes(&huge).is_err());
+    }
+
+    #[test]
+    fn test_trilinear_density() {
+        let bounds = || Aabb::new(Vec3::zero(), Vec3::new(2.0, 1.0, 1.0));
+        assert!(VolumeGrid::new(2, 2, 1, vec![0.0, 1.0], bounds()).is_err());
+        assert!(VolumeGrid::new(0, 1, 1, vec![], bounds()).is_err());
+        let grid = VolumeGrid::new(2, 1, 1, vec![0.0, 1.0], bounds()).unwrap();
+        assert_eq!(grid.density(&Vec3::new(0.5, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.density(&Vec3::new(1.0, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.density(&Vec3::new(1.5, 0.2, 0.9)), 1.0);
+        assert_eq!(grid.density(&Vec3::new(3.0, 0.5, 0.5)), 0.0);
+    }
+}